@@ -1,82 +1,287 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
+use clap::{Parser, ValueEnum};
 use image::ImageFormat;
-use raytracer::raytracer::{element::{Sphere, Plane, Element}, material::{Material, Color, Coloration, Texture, SurfaceType}, geometry::{Point, Vector3}, scene::{Scene, render}, light::{ Light, SphericalLight}};
-
-fn main() {
-
-    let texture_sphere = Texture::load_texture("textures/checkerboard.png").expect("could not load texture");
-    let texture_plane = Texture::load_texture("textures/checkerboard.png").expect("could not load texture");
-    let width: u32 = 800;
-    let height: u32 = 600;
-    let sphere_green = Sphere {
-        center: Point::new(0.0, 0.0, -5.0),
-        radius: 0.75,
-        material: Material { 
-            coloration: Coloration::Color(Color::new(0.4, 1.0, 0.4)), 
-            albedo: 0.18,
-            surface: SurfaceType::Reflective { reflectivity: 0.7 }
+use raytracer::raytracer::aov::Aov;
+use raytracer::raytracer::integrator::Integrator;
+use raytracer::raytracer::output::{HdrFormat, save_hdr};
+use raytracer::raytracer::sampling::{Filter, SamplePattern};
+use raytracer::raytracer::scene::{Scene, render};
+use raytracer::raytracer::tonemap::ToneMapping;
+
+/// Render a scene description file to an image.
+#[derive(Parser)]
+#[command(version, about)]
+struct Cli {
+    /// Scene description file (TOML)
+    scene: PathBuf,
+
+    /// Output image path
+    #[arg(short, long, default_value = "render.png")]
+    output: PathBuf,
+
+    /// Output width in pixels, overriding the scene file
+    #[arg(long, requires = "height")]
+    width: Option<u32>,
+
+    /// Output height in pixels, overriding the scene file
+    #[arg(long, requires = "width")]
+    height: Option<u32>,
+
+    /// Output format; inferred from the output extension when omitted
+    #[arg(short, long, value_enum)]
+    format: Option<OutputFormat>,
+
+    /// Light transport algorithm
+    #[arg(long, value_enum, default_value = "whitted")]
+    integrator: IntegratorArg,
+
+    /// Maximum recursion depth for reflected and refracted rays, or path length
+    #[arg(long)]
+    max_depth: Option<u32>,
+
+    /// Samples per pixel; grid patterns round it down to a square
+    #[arg(short, long)]
+    samples: Option<u32>,
+
+    /// Placement of the samples within a pixel
+    #[arg(long, value_enum, default_value = "regular")]
+    pattern: PatternArg,
+
+    /// Reconstruction filter weighting the samples of a pixel
+    #[arg(long, value_enum, default_value = "box")]
+    filter: FilterArg,
+
+    /// Number of render threads; defaults to every available core
+    #[arg(short = 'j', long)]
+    threads: Option<usize>,
+
+    /// Tone mapping operator for 8-bit outputs
+    #[arg(long, value_enum, default_value = "clamp")]
+    tone_map: ToneMapArg,
+
+    /// Exposure adjustment in stops applied before tone mapping
+    #[arg(long, allow_negative_numbers = true)]
+    exposure: Option<f32>,
+
+    /// Luminance mapped to white by the extended Reinhard operator
+    #[arg(long)]
+    white_point: Option<f32>,
+
+    /// Extra pass to write next to the output, as OUTPUT_<name>: depth,
+    /// normal, albedo, uv, object_id or light<index>; may be repeated
+    #[arg(long = "aov", value_name = "NAME")]
+    aovs: Vec<Aov>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Png,
+    Jpeg,
+    Bmp,
+    Tga,
+    Tiff,
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+// 8-bit formats are gamma-encoded, floating-point ones keep linear values.
+enum Output {
+    Ldr(ImageFormat),
+    Hdr(HdrFormat),
+}
+
+impl From<OutputFormat> for Output {
+    fn from(format: OutputFormat) -> Output {
+        match format {
+            OutputFormat::Png => Output::Ldr(ImageFormat::Png),
+            OutputFormat::Jpeg => Output::Ldr(ImageFormat::Jpeg),
+            OutputFormat::Bmp => Output::Ldr(ImageFormat::Bmp),
+            OutputFormat::Tga => Output::Ldr(ImageFormat::Tga),
+            OutputFormat::Tiff => Output::Ldr(ImageFormat::Tiff),
+            OutputFormat::Exr => Output::Hdr(HdrFormat::OpenExr),
+            OutputFormat::Hdr => Output::Hdr(HdrFormat::Radiance),
+            OutputFormat::Pfm => Output::Hdr(HdrFormat::Pfm),
         }
-    };
+    }
+}
+
+fn infer_format(path: &Path) -> Option<OutputFormat> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    match extension.as_str() {
+        "png" => Some(OutputFormat::Png),
+        "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+        "bmp" => Some(OutputFormat::Bmp),
+        "tga" => Some(OutputFormat::Tga),
+        "tif" | "tiff" => Some(OutputFormat::Tiff),
+        "exr" => Some(OutputFormat::Exr),
+        "hdr" => Some(OutputFormat::Hdr),
+        "pfm" => Some(OutputFormat::Pfm),
+        _ => None,
+    }
+}
 
-    let sphere_blue = Sphere {
-        center: Point::new(-2.0, 1.0, -6.0),
-        radius: 1.5,
-        material: Material { 
-            coloration: Coloration::Texture(texture_sphere), 
-            albedo: 0.58,
-            surface: SurfaceType::Diffuse
+#[derive(Clone, Copy, ValueEnum)]
+enum IntegratorArg {
+    Whitted,
+    Path,
+}
+
+impl From<IntegratorArg> for Integrator {
+    fn from(integrator: IntegratorArg) -> Integrator {
+        match integrator {
+            IntegratorArg::Whitted => Integrator::Whitted,
+            IntegratorArg::Path => Integrator::PathTracing,
         }
-    };
+    }
+}
 
-    let sphere_red = Sphere {
-        center: Point::new(1.0, 1.5, -4.0),
-        radius: 1.5,
-        material: Material { 
-            coloration: Coloration::Color(Color::new(0.8, 0.1, 0.1)), 
-            albedo: 0.18,
-            surface: SurfaceType::Diffuse
+#[derive(Clone, Copy, ValueEnum)]
+enum PatternArg {
+    Regular,
+    Jittered,
+    Halton,
+    Sobol,
+}
+
+impl From<PatternArg> for SamplePattern {
+    fn from(pattern: PatternArg) -> SamplePattern {
+        match pattern {
+            PatternArg::Regular => SamplePattern::Regular,
+            PatternArg::Jittered => SamplePattern::Jittered,
+            PatternArg::Halton => SamplePattern::Halton,
+            PatternArg::Sobol => SamplePattern::Sobol,
         }
+    }
+}
 
-    };
+#[derive(Clone, Copy, ValueEnum)]
+enum FilterArg {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
 
-    let plane_horizontal = Plane {
-        origin: Point::new(0.0, -2.0, -5.0),
-        normal: Vector3::new(0.0, -1.0, 0.0),
-        material: Material { 
-            coloration: Coloration::Texture(texture_plane), 
-            albedo: 0.18,
-            surface:  SurfaceType::Reflective { reflectivity: 0.5 }
+impl From<FilterArg> for Filter {
+    fn from(filter: FilterArg) -> Filter {
+        match filter {
+            FilterArg::Box => Filter::Box,
+            FilterArg::Tent => Filter::Tent,
+            FilterArg::Gaussian => Filter::Gaussian,
+            FilterArg::Mitchell => Filter::Mitchell,
         }
-    };
+    }
+}
 
+#[derive(Clone, Copy, ValueEnum)]
+enum ToneMapArg {
+    Clamp,
+    Reinhard,
+    ExtendedReinhard,
+    Aces,
+    Hable,
+}
 
-    let light_1 = SphericalLight {
-        position: Point::new(-2.0, 5.0, -3.0),
-        color: Color::new(0.9, 0.9, 0.9),
-        intensity: 1000.0
-    };
+impl From<ToneMapArg> for ToneMapping {
+    fn from(tone_map: ToneMapArg) -> ToneMapping {
+        match tone_map {
+            ToneMapArg::Clamp => ToneMapping::Clamp,
+            ToneMapArg::Reinhard => ToneMapping::Reinhard,
+            ToneMapArg::ExtendedReinhard => ToneMapping::ExtendedReinhard,
+            ToneMapArg::Aces => ToneMapping::Aces,
+            ToneMapArg::Hable => ToneMapping::Hable,
+        }
+    }
+}
 
-    let light_2 = SphericalLight {
-        position: Point::new(0.25, 0.0, -2.0),
-        color: Color::new(0.2, 0.2, 0.5),
-        intensity: 250.0
+// AOV files are written in the format of the output when it is a
+// floating-point one, as OpenEXR otherwise.
+fn aov_path(output: &Path, aov: &Aov, format: HdrFormat) -> PathBuf {
+    let stem = output.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let extension = match format {
+        HdrFormat::OpenExr => "exr",
+        HdrFormat::Radiance => "hdr",
+        HdrFormat::Pfm => "pfm",
     };
+    output.with_file_name(format!("{}_{}.{}", stem, aov, extension))
+}
+
+fn run(cli: Cli) -> Result<(), String> {
+    let mut scene = Scene::from_file(&cli.scene)?;
+
+    if let (Some(width), Some(height)) = (cli.width, cli.height) {
+        if width == 0 || height == 0 {
+            return Err(String::from("width and height must be greater than zero"));
+        }
+        scene.set_dimension(width, height);
+    }
+    if let Some(max_depth) = cli.max_depth {
+        scene.max_recursion_depth = max_depth;
+    }
+    if let Some(samples) = cli.samples {
+        if samples == 0 {
+            return Err(String::from("samples must be greater than zero"));
+        }
+        scene.samples = samples;
+    }
+    scene.integrator = cli.integrator.into();
+    scene.sample_pattern = cli.pattern.into();
+    scene.filter = cli.filter.into();
+
+    if let Some(threads) = cli.threads {
+        scene.threads = threads;
+    }
 
+    scene.tone_mapping = cli.tone_map.into();
+    if let Some(exposure) = cli.exposure {
+        scene.exposure = exposure;
+    }
+    if let Some(white_point) = cli.white_point {
+        if white_point <= 0.0 {
+            return Err(String::from("white point must be greater than zero"));
+        }
+        scene.white_point = white_point;
+    }
 
+    let format = cli.format
+        .or_else(|| infer_format(&cli.output))
+        .ok_or_else(|| format!("{}: cannot infer output format, use --format", cli.output.display()))?;
 
-    let elements= vec![
-        Element::Sphere(sphere_green),
-        Element::Sphere(sphere_blue),
-        Element::Sphere(sphere_red),
-        Element::Plane(plane_horizontal),
-    ];
+    for aov in &cli.aovs {
+        aov.validate(&scene).map_err(|e| format!("aov {}", e))?;
+    }
+    scene.aovs = cli.aovs;
 
-    let lights: Vec<Light> = vec![Light::SphericalLight(light_1), Light::SphericalLight(light_2)];
+    let framebuffer = render(&scene);
+    let (saved, aov_format) = match Output::from(format) {
+        Output::Ldr(format) => {
+            let saved = framebuffer
+                .to_image(scene.tone_mapping, scene.exposure, scene.white_point)
+                .save_with_format(&cli.output, format)
+                .map_err(|e| e.to_string());
+            (saved, HdrFormat::OpenExr)
+        },
+        Output::Hdr(format) => (save_hdr(&framebuffer.to_hdr_image(), &cli.output, format), format),
+    };
+    saved.map_err(|e| format!("{}: could not save image: {}", cli.output.display(), e))?;
 
-    let scene = Scene::new(height, width, elements, lights);
-    let image = render(&scene);
+    for (layer, aov) in framebuffer.aovs().iter().enumerate() {
+        let path = aov_path(&cli.output, aov, aov_format);
+        save_hdr(&framebuffer.aov_image(layer), &path, aov_format)
+            .map_err(|e| format!("{}: could not save image: {}", path.display(), e))?;
+    }
+    Ok(())
+}
 
-    let image_path = Path::new("sphere.png");
-    image.save_with_format(image_path, ImageFormat::Png).unwrap();
+fn main() -> ExitCode {
+    match run(Cli::parse()) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::FAILURE
+        }
+    }
 }
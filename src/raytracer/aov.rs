@@ -0,0 +1,115 @@
+use std::fmt;
+use std::str::FromStr;
+
+use rand::Rng;
+
+use super::material::Color;
+use super::ray::{Intersectable, Ray};
+use super::scene::{Scene, light_contribution, light_highlight, shading_point};
+
+// Arbitrary output variable: a quantity of the first surface seen through a
+// pixel, rendered alongside the beauty image. Shading quantities are filtered
+// the same way as the image; depth, normals and ids would be meaningless once
+// averaged across an edge, so they are taken from the ray through the pixel
+// center instead. Background pixels are black.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aov {
+    Depth,        // distance from the camera, in every channel
+    Normal,       // world-space shading normal, components in [-1, 1]
+    Albedo,       // surface color times albedo
+    Uv,           // texture coordinates in red and green
+    ObjectId,     // id of the scene element, in every channel
+    Light(usize), // direct diffuse lighting from the light with this index
+}
+
+impl fmt::Display for Aov {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Aov::Depth => write!(f, "depth"),
+            Aov::Normal => write!(f, "normal"),
+            Aov::Albedo => write!(f, "albedo"),
+            Aov::Uv => write!(f, "uv"),
+            Aov::ObjectId => write!(f, "object_id"),
+            Aov::Light(index) => write!(f, "light{}", index),
+        }
+    }
+}
+
+// Parses the names written by `Display`.
+impl FromStr for Aov {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Aov, String> {
+        match name {
+            "depth" => Ok(Aov::Depth),
+            "normal" => Ok(Aov::Normal),
+            "albedo" => Ok(Aov::Albedo),
+            "uv" => Ok(Aov::Uv),
+            "object_id" => Ok(Aov::ObjectId),
+            _ => name.strip_prefix("light")
+                .and_then(|index| index.parse().ok())
+                .map(Aov::Light)
+                .ok_or_else(|| format!(
+                    "unknown AOV `{}`, expected depth, normal, albedo, uv, object_id or light<index>", name)),
+        }
+    }
+}
+
+impl Aov {
+
+    // Whether the AOV is averaged over the samples of a pixel with the
+    // reconstruction filter.
+    pub fn is_filtered(&self) -> bool {
+        match self {
+            Aov::Depth | Aov::Normal | Aov::ObjectId => false,
+            Aov::Albedo | Aov::Uv | Aov::Light(_) => true,
+        }
+    }
+
+    pub fn validate(&self, scene: &Scene) -> Result<(), String> {
+        match self {
+            Aov::Light(index) if *index >= scene.lights.len() =>
+                Err(format!("{}: the scene has {} lights", self, scene.lights.len())),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Values of the AOVs of the scene that are, or are not, filtered for one
+// camera ray; the others are left black.
+pub(crate) fn evaluate<R: Rng>(scene: &Scene, ray: &Ray, filtered: bool, rng: &mut R) -> Vec<Color> {
+    let intersection = match scene.trace(ray) {
+        Some(intersection) => intersection,
+        None => return vec![Color::black(); scene.aovs.len()],
+    };
+    let point = shading_point(ray, &intersection);
+    let (surface_normal, _) = point.outward_normals();
+
+    scene.aovs
+        .iter()
+        .map(|aov| match aov {
+            _ if aov.is_filtered() != filtered => Color::black(),
+            Aov::Depth => {
+                let depth = intersection.distance as f32;
+                Color::new(depth, depth, depth)
+            },
+            Aov::Normal => {
+                let (x, y, z) = surface_normal.coordinate();
+                Color::new(x as f32, y as f32, z as f32)
+            },
+            Aov::Albedo => point.material.color * point.material.albedo,
+            Aov::Uv => {
+                let uv = point.element.texture_coords(&point.hit_point);
+                Color::new(uv.x, uv.y, 0.0)
+            },
+            Aov::ObjectId => {
+                let id = scene.object_id(intersection.index) as f32;
+                Color::new(id, id, id)
+            },
+            Aov::Light(index) => {
+                let light = &scene.lights[*index];
+                light_contribution(scene, light, &point, rng) + light_highlight(scene, light, &point, rng)
+            },
+        })
+        .collect()
+}
@@ -0,0 +1,121 @@
+use super::element::PlacedElement;
+use super::geometry::{Point, Vector3};
+use super::material::{Coloration, TextureCoords};
+use super::ray::Intersectable;
+
+// Detail added to the shading normal of a surface without changing its
+// geometry. Both maps are laid out along the texture coordinates of the
+// element, whose tangent frame is measured by finite differences.
+
+// Distance between the points compared to measure how the texture
+// coordinates change over a surface.
+const UV_RATE_STEP: f64 = 1e-3;
+// Step in uv units between the heights compared to measure the slope of a
+// bump map.
+const HEIGHT_STEP: f32 = 1e-3;
+
+// Tangent-space normals, in the OpenGL convention: red along increasing u,
+// green up the image (decreasing v) and blue along the normal, each mapped
+// from [0, 1] to [-1, 1]. The coloration should hold linear values.
+pub struct NormalMap {
+    pub coloration: Coloration,
+    pub strength: f32, // scales the tilt of the normals
+}
+
+// Height field from the luminance of a coloration; `scale` is the height in
+// scene units of a luminance of 1.
+pub struct BumpMap {
+    pub height: Coloration,
+    pub scale: f32,
+}
+
+impl NormalMap {
+
+    fn perturb(&self, normal: Vector3, dpdu: Vector3, dpdv: Vector3, texture_coord: &TextureCoords, hit_point: &Point, footprint: f32) -> Vector3 {
+        let color = self.coloration.sample(texture_coord, hit_point, footprint);
+        let x = ((2.0 * color.red - 1.0) * self.strength) as f64;
+        let y = ((2.0 * color.green - 1.0) * self.strength) as f64;
+        let z = ((2.0 * color.blue - 1.0) as f64).max(0.0);
+
+        // the map is laid out along the coordinates it is looked up at
+        let (dpdu, dpdv) = self.coloration.tangents(dpdu, dpdv);
+        let tangent = (dpdu - normal * normal.dot(&dpdu)).normalize();
+        let up = -dpdv;
+        let bitangent = (up - normal * normal.dot(&up) - tangent * tangent.dot(&up)).normalize();
+        let perturbed = tangent * x + bitangent * y + normal * z;
+        if perturbed.length() < 1e-9 { normal } else { perturbed.normalize() }
+    }
+}
+
+impl BumpMap {
+
+    fn perturb(&self, normal: Vector3, dpdu: Vector3, dpdv: Vector3, texture_coord: &TextureCoords, hit_point: &Point, footprint: f32) -> Vector3 {
+        let height = |du: f32, dv: f32| {
+            let coord = TextureCoords { x: texture_coord.x + du, y: texture_coord.y + dv };
+            let point = *hit_point + dpdu * du as f64 + dpdv * dv as f64;
+            (self.height.sample(&coord, &point, footprint).luminance() * self.scale) as f64
+        };
+        let base = height(0.0, 0.0);
+        let dhdu = (height(HEIGHT_STEP, 0.0) - base) / HEIGHT_STEP as f64;
+        let dhdv = (height(0.0, HEIGHT_STEP) - base) / HEIGHT_STEP as f64;
+
+        // tangents of the displaced surface, ignoring how the normal turns
+        let perturbed = (dpdu + normal * dhdu).cross(&(dpdv + normal * dhdv));
+        if perturbed.length() < 1e-9 {
+            return normal;
+        }
+        let perturbed = perturbed.normalize();
+        if perturbed.dot(&normal) < 0.0 { -perturbed } else { perturbed }
+    }
+}
+
+// Normal to shade a hit with, the geometric `normal` perturbed by the normal
+// and bump maps of the material. Maps are filtered over `footprint`.
+pub(crate) fn shading_normal(element: &PlacedElement, hit_point: &Point, normal: Vector3, footprint: f32) -> Vector3 {
+    let material = element.material();
+    if material.normal_map.is_none() && material.bump_map.is_none() {
+        return normal;
+    }
+    let (dpdu, dpdv) = match texture_derivatives(element, hit_point, normal) {
+        Some(derivatives) => derivatives,
+        None => return normal,
+    };
+
+    let texture_coord = element.texture_coords(hit_point);
+    let mut shading_normal = normal;
+    if let Some(normal_map) = &material.normal_map {
+        shading_normal = normal_map.perturb(shading_normal, dpdu, dpdv, &texture_coord, hit_point, footprint);
+    }
+    if let Some(bump_map) = &material.bump_map {
+        shading_normal = bump_map.perturb(shading_normal, dpdu, dpdv, &texture_coord, hit_point, footprint);
+    }
+    shading_normal
+}
+
+// Derivatives of the position on the surface with respect to u and v, None
+// where the texture coordinates do not change over the surface.
+fn texture_derivatives(element: &PlacedElement, hit_point: &Point, normal: Vector3) -> Option<(Vector3, Vector3)> {
+    let texture_coord = element.texture_coords(hit_point);
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let (du_t, dv_t) = uv_rate(element, &texture_coord, hit_point, tangent);
+    let (du_b, dv_b) = uv_rate(element, &texture_coord, hit_point, bitangent);
+
+    // invert the change of uv along the tangent and bitangent
+    let determinant = du_t * dv_b - du_b * dv_t;
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let dpdu = (tangent * dv_b - bitangent * dv_t) * determinant.recip();
+    let dpdv = (bitangent * du_t - tangent * du_b) * determinant.recip();
+    Some((dpdu, dpdv))
+}
+
+// Change of the texture coordinates, `texture_coord` at the hit point, per
+// unit distance along `axis`, by finite differences.
+pub(crate) fn uv_rate(element: &PlacedElement, texture_coord: &TextureCoords, hit_point: &Point, axis: Vector3) -> (f64, f64) {
+    let other = element.texture_coords(&(*hit_point + axis * UV_RATE_STEP));
+    // coordinates wrap around on closed surfaces
+    let du = other.x - texture_coord.x;
+    let dv = other.y - texture_coord.y;
+    ((du - du.round()) as f64 / UV_RATE_STEP, (dv - dv.round()) as f64 / UV_RATE_STEP)
+}
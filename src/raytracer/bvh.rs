@@ -0,0 +1,341 @@
+use super::element::{Element, PlacedElement};
+use super::geometry::{BoundingBox, Point, Vector3};
+use super::ray::{Intersectable, Intersection, Ray};
+
+const SAH_BINS: usize = 12;
+const MAX_LEAF_SIZE: usize = 4;
+const TRAVERSAL_COST: f64 = 1.0;
+const INTERSECTION_COST: f64 = 1.0;
+
+// Nodes are stored depth-first: the left child of an interior node is the
+// node right after it, the right child is at `right`.
+enum Node {
+    Leaf { bounds: BoundingBox, first: usize, count: usize },
+    Interior { bounds: BoundingBox, right: usize },
+}
+
+impl Node {
+    fn bounds(&self) -> &BoundingBox {
+        match self {
+            Node::Leaf { bounds, .. } => bounds,
+            Node::Interior { bounds, .. } => bounds,
+        }
+    }
+}
+
+struct Split {
+    axis: usize,
+    bin: usize,
+    min: f64,
+    scale: f64,
+}
+
+impl Split {
+    fn bin(&self, centroid: &Point) -> usize {
+        (((axis_value(centroid, self.axis) - self.min) * self.scale) as usize).min(SAH_BINS - 1)
+    }
+}
+
+struct PrimitiveInfo {
+    index: usize,
+    bounds: BoundingBox,
+    centroid: Point,
+}
+
+// Bounding volume hierarchy over the elements of a scene, built with the
+// surface area heuristic. Elements without a bounding box (planes) can not be
+// put in the tree and are tested against every ray.
+pub struct Bvh {
+    nodes: Vec<Node>,
+    indices: Vec<usize>,
+    unbounded: Vec<usize>,
+}
+
+impl Bvh {
+
+    pub fn build(elements: &[Element]) -> Self {
+        Self::from_bounds(elements.iter().map(Intersectable::bounding_box))
+    }
+
+    // Tree over primitives of any kind, given the bounding box of each in
+    // order; they are referred to by their index.
+    pub fn from_bounds<I: IntoIterator<Item = Option<BoundingBox>>>(bounds: I) -> Self {
+        let mut primitives = Vec::new();
+        let mut unbounded = Vec::new();
+        for (index, bounds) in bounds.into_iter().enumerate() {
+            match bounds {
+                Some(bounds) => primitives.push(PrimitiveInfo { index, bounds, centroid: bounds.centroid() }),
+                None => unbounded.push(index),
+            }
+        }
+
+        let mut bvh = Self { nodes: Vec::new(), indices: Vec::with_capacity(primitives.len()), unbounded };
+        if !primitives.is_empty() {
+            bvh.build_recursive(&mut primitives);
+        }
+        bvh
+    }
+
+    fn build_recursive(&mut self, primitives: &mut [PrimitiveInfo]) -> usize {
+        let bounds = primitives.iter().fold(BoundingBox::empty(), |b, p| b.union(&p.bounds));
+        let node_index = self.nodes.len();
+
+        let mid = match find_split(primitives, &bounds) {
+            Some(split) => partition(primitives, |p| split.bin(&p.centroid) < split.bin),
+            None => 0,
+        };
+        match mid {
+            0 => {
+                self.nodes.push(Node::Leaf { bounds, first: self.indices.len(), count: primitives.len() });
+                self.indices.extend(primitives.iter().map(|p| p.index));
+            },
+            mid => {
+                self.nodes.push(Node::Interior { bounds, right: 0 });
+                let (left, right) = primitives.split_at_mut(mid);
+                self.build_recursive(left);
+                let right_index = self.build_recursive(right);
+                self.nodes[node_index] = Node::Interior { bounds, right: right_index };
+            },
+        }
+        node_index
+    }
+
+    // Bounds of every primitive, None if some are unbounded or there are none.
+    pub fn bounds(&self) -> Option<BoundingBox> {
+        match self.nodes.first() {
+            Some(node) if self.unbounded.is_empty() => Some(*node.bounds()),
+            _ => None,
+        }
+    }
+
+    pub fn intersect<'a>(&self, elements: &'a [Element], ray: &Ray) -> Option<Intersection<'a>> {
+        self.closest(ray, |index| {
+            let element = &elements[index];
+            element.intersect(ray).map(|distance| Intersection::new(distance, PlacedElement::new(element), index))
+        })
+    }
+
+    // Closest of the intersections of the ray with the primitives, given by
+    // `hit` for the primitive at an index.
+    pub fn closest<'a, F: FnMut(usize) -> Option<Intersection<'a>>>(&self, ray: &Ray, mut hit: F) -> Option<Intersection<'a>> {
+        let mut closest: Option<Intersection<'a>> = None;
+        let mut max_distance = f64::INFINITY;
+
+        for &index in &self.unbounded {
+            if let Some(intersection) = hit(index) {
+                if intersection.distance < max_distance {
+                    max_distance = intersection.distance;
+                    closest = Some(intersection);
+                }
+            }
+        }
+
+        if self.nodes.is_empty() {
+            return closest;
+        }
+
+        let (x, y, z) = ray.direction.coordinate();
+        let inverse_direction = Vector3::new(x.recip(), y.recip(), z.recip());
+
+        let mut stack = vec![0];
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            if node.bounds().intersect(&ray.origin, &inverse_direction, max_distance).is_none() {
+                continue;
+            }
+            match node {
+                Node::Leaf { first, count, .. } => {
+                    for &index in &self.indices[*first..*first + *count] {
+                        if let Some(intersection) = hit(index) {
+                            if intersection.distance < max_distance {
+                                max_distance = intersection.distance;
+                                closest = Some(intersection);
+                            }
+                        }
+                    }
+                },
+                Node::Interior { right, .. } => {
+                    // visit the nearer child first so the farther one is more likely to be culled
+                    let left = node_index + 1;
+                    let t_left = self.nodes[left].bounds().intersect(&ray.origin, &inverse_direction, max_distance);
+                    let t_right = self.nodes[*right].bounds().intersect(&ray.origin, &inverse_direction, max_distance);
+                    match (t_left, t_right) {
+                        (Some(l), Some(r)) if l <= r => { stack.push(*right); stack.push(left); },
+                        (Some(_), Some(_)) => { stack.push(left); stack.push(*right); },
+                        (Some(_), None) => stack.push(left),
+                        (None, Some(_)) => stack.push(*right),
+                        (None, None) => {},
+                    }
+                },
+            }
+        }
+
+        closest
+    }
+}
+
+fn axis_value(point: &Point, axis: usize) -> f64 {
+    let (x, y, z) = point.coordinate();
+    match axis {
+        0 => x,
+        1 => y,
+        _ => z,
+    }
+}
+
+// Binned SAH: returns the best split, or None when making a leaf is cheaper
+// than any split.
+fn find_split(primitives: &[PrimitiveInfo], bounds: &BoundingBox) -> Option<Split> {
+    if primitives.len() <= MAX_LEAF_SIZE {
+        return None;
+    }
+
+    let centroid_bounds = primitives.iter().fold(BoundingBox::empty(), |b, p| b.include(&p.centroid));
+    let leaf_cost = INTERSECTION_COST * primitives.len() as f64;
+    let parent_area = bounds.surface_area();
+
+    let mut best: Option<(f64, Split)> = None;
+    for axis in 0..3 {
+        let min = axis_value(&centroid_bounds.min, axis);
+        let max = axis_value(&centroid_bounds.max, axis);
+        if max - min <= f64::EPSILON {
+            continue;
+        }
+
+        let mut bins = [(BoundingBox::empty(), 0usize); SAH_BINS];
+        let binning = Split { axis, bin: 0, min, scale: SAH_BINS as f64 / (max - min) };
+        for p in primitives {
+            let bin = binning.bin(&p.centroid);
+            bins[bin].0 = bins[bin].0.union(&p.bounds);
+            bins[bin].1 += 1;
+        }
+
+        for split in 1..SAH_BINS {
+            let (left_bounds, left_count) = bins[..split].iter()
+                .fold((BoundingBox::empty(), 0), |(b, c), (bin, n)| (b.union(bin), c + n));
+            let (right_bounds, right_count) = bins[split..].iter()
+                .fold((BoundingBox::empty(), 0), |(b, c), (bin, n)| (b.union(bin), c + n));
+            if left_count == 0 || right_count == 0 {
+                continue;
+            }
+
+            let cost = TRAVERSAL_COST + INTERSECTION_COST *
+                (left_bounds.surface_area() * left_count as f64 + right_bounds.surface_area() * right_count as f64) / parent_area;
+            if best.as_ref().is_none_or(|(best_cost, _)| cost < *best_cost) {
+                best = Some((cost, Split { bin: split, ..binning }));
+            }
+        }
+    }
+
+    match best {
+        Some((cost, split)) if cost < leaf_cost || primitives.len() > 4 * MAX_LEAF_SIZE => Some(split),
+        _ => None,
+    }
+}
+
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], predicate: F) -> usize {
+    let mut mid = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+    use super::*;
+    use crate::raytracer::element::{Plane, Sphere, Triangle};
+    use crate::raytracer::material::{Color, Material};
+
+    fn random_point(rng: &mut SmallRng, extent: f64) -> Point {
+        Point::new(
+            rng.random_range(-extent..extent),
+            rng.random_range(-extent..extent),
+            rng.random_range(-extent..extent),
+        )
+    }
+
+    fn random_direction(rng: &mut SmallRng) -> Vector3 {
+        loop {
+            let (x, y, z): (f64, f64, f64) = (rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0), rng.random_range(-1.0..1.0));
+            let direction = Vector3::new(x, y, z);
+            if direction.length() > 0.1 && direction.length() <= 1.0 {
+                return direction.normalize();
+            }
+        }
+    }
+
+    fn scene(rng: &mut SmallRng) -> Vec<Element> {
+        let mut elements = Vec::new();
+        for _ in 0..200 {
+            elements.push(Element::Sphere(Sphere {
+                center: random_point(rng, 10.0),
+                radius: rng.random_range(0.1..1.0),
+                material: Material::diffuse(Color::black(), 0.18),
+            }));
+            let v0 = random_point(rng, 10.0);
+            elements.push(Element::Triangle(Triangle {
+                v0,
+                v1: v0 + random_direction(rng) * 2.0,
+                v2: v0 + random_direction(rng) * 2.0,
+                material: Material::diffuse(Color::black(), 0.18),
+            }));
+        }
+        elements.push(Element::Plane(Plane {
+            origin: Point::new(0.0, -12.0, 0.0),
+            normal: Vector3::new(0.0, -1.0, 0.0),
+            material: Material::diffuse(Color::black(), 0.18),
+        }));
+        elements
+    }
+
+    fn brute_force(elements: &[Element], ray: &Ray) -> Option<(usize, f64)> {
+        elements.iter()
+            .enumerate()
+            .filter_map(|(index, element)| element.intersect(ray).map(|distance| (index, distance)))
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+    }
+
+    #[test]
+    fn finds_the_same_hits_as_brute_force() {
+        let mut rng = SmallRng::seed_from_u64(1);
+        let elements = scene(&mut rng);
+        let bvh = Bvh::build(&elements);
+
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let ray = Ray::new(random_point(&mut rng, 15.0), random_direction(&mut rng));
+            let expected = brute_force(&elements, &ray);
+            let found = bvh.intersect(&elements, &ray).map(|i| (i.index, i.distance));
+            match (expected, found) {
+                (Some((expected_index, expected_distance)), Some((index, distance))) => {
+                    assert_eq!(index, expected_index);
+                    assert!((distance - expected_distance).abs() < 1e-9);
+                    hits += 1;
+                },
+                (None, None) => {},
+                _ => panic!("bvh found {:?}, brute force {:?}", found, expected),
+            }
+        }
+        assert!(hits > 100, "too few rays hit the scene to compare: {}", hits);
+    }
+
+    #[test]
+    fn handles_empty_and_unbounded_scenes() {
+        let ray = Ray::new(Point::zero(), Vector3::new(0.0, -1.0, 0.0));
+        assert!(Bvh::build(&[]).intersect(&[], &ray).is_none());
+
+        let elements = vec![Element::Plane(Plane {
+            origin: Point::new(0.0, -1.0, 0.0),
+            normal: Vector3::new(0.0, -1.0, 0.0),
+            material: Material::diffuse(Color::black(), 0.18),
+        })];
+        let hit = Bvh::build(&elements).intersect(&elements, &ray).expect("the plane is below the ray origin");
+        assert!((hit.distance - 1.0).abs() < 1e-12);
+    }
+}
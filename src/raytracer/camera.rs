@@ -1,58 +1,147 @@
 
 use crate::raytracer::geometry::Vector3;
-use super::{geometry::Point, ray::Ray};
+use super::{geometry::Point, ray::Ray, sampling::concentric_disk};
+
+// Field of view of the historical default camera, whose image plane is one
+// unit away and one unit high.
+const DEFAULT_VFOV: f64 = 53.13010235415598; // 2 * atan(1/2) in degrees
 
 pub struct Camera {
     look_from: Point,
     look_at: Point,
     vup: Vector3,
     vfov: f64, // vertical field-of-view in degrees
-    aspect_ratio: f64 
-} 
+    aspect_ratio: f64,
+    aperture: f64, // lens radius, 0 for a pinhole camera
+    focus_distance: Option<f64>, // defaults to the distance to look_at
+}
 
 impl Camera {
 
-    pub fn default_with_aspect_ratio(aspect_ratio: f64) -> Self {
-        let vfov: f64 = 90.0; //90° 
+    pub fn new(look_from: Point, look_at: Point, vup: Vector3, vfov: f64, aspect_ratio: f64) -> Self {
+        Self { look_from, look_at, vup, vfov, aspect_ratio, aperture: 0.0, focus_distance: None }
+    }
 
+    pub fn default_with_aspect_ratio(aspect_ratio: f64) -> Self {
         let look_from = Point::zero();
         let look_at = Point::new(0.0, 0.0, -1.0);
 
-        let vup =  Vector3::new(0.0, -1.0, 0.0);
+        let vup =  Vector3::new(0.0, 1.0, 0.0);
+
+        Self::new(look_from, look_at, vup, DEFAULT_VFOV, aspect_ratio)
+    }
+
+    pub fn look_from(mut self, look_from: Point) -> Self {
+        self.look_from = look_from;
+        self
+    }
+
+    pub fn look_at(mut self, look_at: Point) -> Self {
+        self.look_at = look_at;
+        self
+    }
+
+    pub fn vup(mut self, vup: Vector3) -> Self {
+        self.vup = vup;
+        self
+    }
+
+    pub fn vfov(mut self, vfov: f64) -> Self {
+        self.vfov = vfov;
+        self
+    }
+
+    pub fn aperture(mut self, aperture: f64) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn focus_distance(mut self, focus_distance: f64) -> Self {
+        self.focus_distance = Some(focus_distance);
+        self
+    }
 
-        Self { look_from, look_at, vup, vfov, aspect_ratio}
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.vfov > 0.0 && self.vfov < 180.0) {
+            return Err(format!("vfov: must be between 0 and 180 degrees, got {}", self.vfov));
+        }
+        let forward = self.look_at - self.look_from;
+        if forward.length() == 0.0 {
+            return Err(String::from("look_at: must differ from look_from"));
+        }
+        if forward.cross(&self.vup).length() == 0.0 {
+            return Err(String::from("vup: must not be parallel to the viewing direction"));
+        }
+        if self.aperture < 0.0 {
+            return Err(format!("aperture: must not be negative, got {}", self.aperture));
+        }
+        if let Some(focus_distance) = self.focus_distance {
+            if focus_distance <= 0.0 {
+                return Err(format!("focus_distance: must be greater than zero, got {}", focus_distance));
+            }
+        }
+        Ok(())
     }
 
+    // Full height and width of the image plane at unit distance.
     fn focal_dimension(&self) -> (f64, f64) {
         let theta = (self.vfov / 2.0).to_radians();
-        let height = theta.tan();
+        let height = 2.0 * theta.tan();
         let width =  self.aspect_ratio * height;
         (height, width)
     }
 
+    // (right, up, forward)
     fn coordinate_system(&self) -> (Vector3, Vector3, Vector3) {
         let w = (self.look_at - self.look_from).normalize();
-        let u = self.vup.cross(&w).normalize();
-        let v = w.cross(&u);
+        let u = w.cross(&self.vup).normalize();
+        let v = u.cross(&w);
         (u,v,w)
     }
 
-    pub fn get_ray(&self, x: f64, y: f64) -> Ray {
+    // x and y are in [0, 1], with y going down the image. `lens` is a point
+    // in the unit square mapped onto the aperture disk; it is ignored by a
+    // pinhole camera.
+    pub fn get_ray(&self, x: f64, y: f64, lens: (f64, f64)) -> Ray {
 
         let origin = self.look_from;
-        let (u,v, _) = self.coordinate_system();
+        let (u,v, w) = self.coordinate_system();
         let (height, width) = self.focal_dimension();
-        let lower_left_corner = self.look_at - (u * (width / 2.0)) - (v * (height / 2.0));
 
-        let direction = lower_left_corner + (x * width * u) + ( y * height * v) - origin;
+        let direction = w + ((x - 0.5) * width * u) + ((0.5 - y) * height * v);
+
+        if self.aperture <= 0.0 {
+            return Ray::new(
+                origin,
+                direction.normalize(),
+            );
+        }
+
+        // every ray through the lens converges on the plane of focus
+        let focus_distance = self.focus_distance
+            .unwrap_or_else(|| (self.look_at - self.look_from).length());
+        let focus_point = origin + direction * focus_distance;
+        let (lens_x, lens_y) = concentric_disk(lens);
+        let lens_point = origin + u * (lens_x * self.aperture) + v * (lens_y * self.aperture);
 
         Ray::new(
-            origin,
-            direction.normalize(),
+            lens_point,
+            (focus_point - lens_point).normalize(),
         )
     }
 
+    // Angle covered by one pixel at the center of an image `height` pixels
+    // high, the spread of the ray cones of camera rays.
+    pub fn pixel_spread(&self, height: u32) -> f64 {
+        let (focal_height, _) = self.focal_dimension();
+        (focal_height / height as f64).atan()
+    }
+
     pub fn get_aspect_ratio(&self) -> f64 {
         self.aspect_ratio
     }
-}
\ No newline at end of file
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f64) {
+        self.aspect_ratio = aspect_ratio;
+    }
+}
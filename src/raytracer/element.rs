@@ -1,19 +1,36 @@
+use std::sync::Arc;
 
-
+use super::bvh::Bvh;
 use super::material::{Material, TextureCoords};
-use super::geometry::{Point, Vector3};
-use super::ray::{Intersectable, Ray};
+use super::geometry::{BoundingBox, Point, Transform, Vector3};
+use super::ray::{Intersectable, Intersection, Ray};
 
 pub enum Element {
     Sphere(Sphere),
-    Plane(Plane)
+    Plane(Plane),
+    Triangle(Triangle),
+    MeshTriangle(MeshTriangle)
 }
 
 impl Element {
     pub fn material(&self) -> &Material {
         match self {
             Element::Sphere(s) => &s.material,
-            Element::Plane(p) => &p.material
+            Element::Plane(p) => &p.material,
+            Element::Triangle(t) => &t.material,
+            Element::MeshTriangle(t) => &t.mesh.material
+        }
+    }
+}
+
+impl Element {
+
+    // Normal of the surface itself, which the interpolated normals of meshes
+    // only approximate; it tells the sides of the surface apart.
+    pub fn face_normal(&self, hit_point: &Point) -> Vector3 {
+        match self {
+            Element::MeshTriangle(t) => t.face_normal(hit_point),
+            _ => self.surface_normal(hit_point),
         }
     }
 }
@@ -22,23 +39,36 @@ impl Intersectable for Element {
     fn intersect(&self, ray: &Ray) -> Option<f64> {
         match self {
             Element::Sphere(s) => s.intersect(ray),
-            Element::Plane(p) => p.intersect(ray)
-            
+            Element::Plane(p) => p.intersect(ray),
+            Element::Triangle(t) => t.intersect(ray),
+            Element::MeshTriangle(t) => t.intersect(ray)
         }
     }
 
     fn surface_normal(&self, hit_point: &Point) -> Vector3 {
         match self {
             Element::Sphere(s) => s.surface_normal(hit_point),
-            Element::Plane(p) => p.surface_normal(hit_point)
-            
+            Element::Plane(p) => p.surface_normal(hit_point),
+            Element::Triangle(t) => t.surface_normal(hit_point),
+            Element::MeshTriangle(t) => t.surface_normal(hit_point)
         }
     }
 
     fn texture_coords(&self, hit_point: &Point) -> super::material::TextureCoords {
         match self {
             Element::Sphere(s) => s.texture_coords(hit_point),
-            Element::Plane(p) => p.texture_coords(hit_point)
+            Element::Plane(p) => p.texture_coords(hit_point),
+            Element::Triangle(t) => t.texture_coords(hit_point),
+            Element::MeshTriangle(t) => t.texture_coords(hit_point)
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        match self {
+            Element::Sphere(s) => s.bounding_box(),
+            Element::Plane(p) => p.bounding_box(),
+            Element::Triangle(t) => t.bounding_box(),
+            Element::MeshTriangle(t) => t.bounding_box()
         }
     }
 }
@@ -55,7 +85,7 @@ impl Intersectable for Plane {
         let denom = normal.dot(&ray.direction.normalize());
         if denom > 1e-6 {
             let v = self.origin - ray.origin;
-            let distance = v.dot(&normal) / denom;
+            let distance = v.dot(normal) / denom;
             if distance > 0.0 {
                 return Some(distance);
             }
@@ -83,6 +113,10 @@ impl Intersectable for Plane {
         }
 
     }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        None
+    }
 }
 
 pub struct Sphere {
@@ -103,14 +137,14 @@ impl Intersectable for Sphere {
         let radius_square = self.radius * self.radius;
 
         if a_square > radius_square {
-            return  None;
+            return None;
         }
         let tickness = (radius_square - a_square).sqrt();
 
         let t0 = b_length - tickness;
         let t1 = b_length + tickness;
         if t0 < 0.0 && t1 < 0.0 {
-            return None;
+            None
         }
         else if t0 < 0.0 {
             Some(t1)
@@ -134,4 +168,404 @@ impl Intersectable for Sphere {
             y: (y / self.radius).acos() as f32 / std::f32::consts::PI
         }
     }
-}
\ No newline at end of file
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let extent = Vector3::new(self.radius, self.radius, self.radius);
+        Some(BoundingBox::new(self.center - extent, self.center + extent))
+    }
+}
+
+// Möller–Trumbore ray/triangle intersection, hits both faces.
+fn intersect_triangle(ray: &Ray, v0: Point, v1: Point, v2: Point) -> Option<f64> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction.cross(&edge2);
+    let determinant = edge1.dot(&p);
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse_determinant = determinant.recip();
+
+    let t = ray.origin - v0;
+    let u = t.dot(&p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = t.cross(&edge1);
+    let v = ray.direction.dot(&q) * inverse_determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let distance = edge2.dot(&q) * inverse_determinant;
+    if distance > 0.0 {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+// Barycentric weights of the hit point for v0, v1 and v2.
+fn barycentric(hit_point: &Point, v0: Point, v1: Point, v2: Point) -> (f64, f64, f64) {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let hit_vec = *hit_point - v0;
+    let d11 = edge1.dot(&edge1);
+    let d12 = edge1.dot(&edge2);
+    let d22 = edge2.dot(&edge2);
+    let dh1 = hit_vec.dot(&edge1);
+    let dh2 = hit_vec.dot(&edge2);
+    let denom = d11 * d22 - d12 * d12;
+
+    let v = (d22 * dh1 - d12 * dh2) / denom;
+    let w = (d11 * dh2 - d12 * dh1) / denom;
+    (1.0 - v - w, v, w)
+}
+
+fn triangle_bounding_box(v0: Point, v1: Point, v2: Point) -> BoundingBox {
+    BoundingBox::empty().include(&v0).include(&v1).include(&v2)
+}
+
+// Vertices are in counter-clockwise order when seen from the front face.
+pub struct Triangle {
+    pub v0: Point,
+    pub v1: Point,
+    pub v2: Point,
+    pub material: Material
+}
+
+impl Intersectable for Triangle {
+
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        intersect_triangle(ray, self.v0, self.v1, self.v2)
+    }
+
+    fn surface_normal(&self, _: &Point) -> Vector3 {
+        (self.v1 - self.v0).cross(&(self.v2 - self.v0)).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (_, v, w) = barycentric(hit_point, self.v0, self.v1, self.v2);
+        TextureCoords {
+            x: v as f32,
+            y: w as f32,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        Some(triangle_bounding_box(self.v0, self.v1, self.v2))
+    }
+}
+
+// Indexed triangle mesh. Normals and UVs are per vertex and optional: without
+// normals faces are flat shaded, without UVs the barycentric coordinates are
+// used as texture coordinates.
+pub struct Mesh {
+    pub positions: Vec<Point>,
+    pub normals: Vec<Vector3>,
+    pub uvs: Vec<TextureCoords>,
+    pub indices: Vec<[usize; 3]>,
+    pub material: Material
+}
+
+impl Mesh {
+
+    pub fn validate(&self) -> Result<(), String> {
+        let vertex_count = self.positions.len();
+        if !self.normals.is_empty() && self.normals.len() != vertex_count {
+            return Err(format!("normals: expected {} normals, got {}", vertex_count, self.normals.len()));
+        }
+        if !self.uvs.is_empty() && self.uvs.len() != vertex_count {
+            return Err(format!("uvs: expected {} uvs, got {}", vertex_count, self.uvs.len()));
+        }
+        for (face, indices) in self.indices.iter().enumerate() {
+            if let Some(index) = indices.iter().find(|&&i| i >= vertex_count) {
+                return Err(format!("indices: face {} references vertex {} but the mesh has {} vertices", face, index, vertex_count));
+            }
+        }
+        Ok(())
+    }
+
+    // A mesh is added to the scene as one element per face, all sharing the
+    // mesh buffers, so that its faces are sorted into the scene BVH.
+    pub fn into_elements(self) -> Vec<Element> {
+        let face_count = self.indices.len();
+        let mesh = Arc::new(self);
+        (0..face_count)
+            .map(|index| Element::MeshTriangle(MeshTriangle { mesh: mesh.clone(), index }))
+            .collect()
+    }
+}
+
+pub struct MeshTriangle {
+    pub mesh: Arc<Mesh>,
+    pub index: usize
+}
+
+impl MeshTriangle {
+
+    fn vertices(&self) -> (Point, Point, Point) {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        (self.mesh.positions[i0], self.mesh.positions[i1], self.mesh.positions[i2])
+    }
+
+    // Turned to the side of the vertex normals, whatever the winding.
+    fn face_normal(&self, hit_point: &Point) -> Vector3 {
+        let (v0, v1, v2) = self.vertices();
+        let normal = (v1 - v0).cross(&(v2 - v0)).normalize();
+        if !self.mesh.normals.is_empty() && normal.dot(&self.surface_normal(hit_point)) < 0.0 {
+            return -normal;
+        }
+        normal
+    }
+}
+
+impl Intersectable for MeshTriangle {
+
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        let (v0, v1, v2) = self.vertices();
+        intersect_triangle(ray, v0, v1, v2)
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        let (v0, v1, v2) = self.vertices();
+        if self.mesh.normals.is_empty() {
+            return (v1 - v0).cross(&(v2 - v0)).normalize();
+        }
+
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let normals = &self.mesh.normals;
+        let (u, v, w) = barycentric(hit_point, v0, v1, v2);
+        (normals[i0] * u + normals[i1] * v + normals[i2] * w).normalize()
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        let (v0, v1, v2) = self.vertices();
+        let (u, v, w) = barycentric(hit_point, v0, v1, v2);
+        if self.mesh.uvs.is_empty() {
+            return TextureCoords {
+                x: v as f32,
+                y: w as f32,
+            };
+        }
+
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let uvs = &self.mesh.uvs;
+        let (u, v, w) = (u as f32, v as f32, w as f32);
+        TextureCoords {
+            x: uvs[i0].x * u + uvs[i1].x * v + uvs[i2].x * w,
+            y: uvs[i0].y * u + uvs[i1].y * v + uvs[i2].y * w,
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let (v0, v1, v2) = self.vertices();
+        Some(triangle_bounding_box(v0, v1, v2))
+    }
+}
+
+// Elements shared by every instance placing them in the scene. An object has
+// a BVH of its own, so that each instance is a single entry of the scene BVH
+// however many elements the object has.
+pub struct Object {
+    elements: Vec<Element>,
+    bvh: Bvh,
+}
+
+impl Object {
+
+    pub fn new(elements: Vec<Element>) -> Self {
+        let bvh = Bvh::build(&elements);
+        Self { elements, bvh }
+    }
+
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+}
+
+// Object placed in the scene by an object-to-world transform. Rays are brought
+// into object space to be intersected.
+pub struct Instance {
+    pub object: Arc<Object>,
+    transform: Transform,
+    to_object: Transform,
+}
+
+impl Instance {
+
+    pub fn new(object: Arc<Object>, transform: Transform) -> Self {
+        Self { object, transform, to_object: transform.inverse() }
+    }
+
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    // Closest element of the object hit by a world-space ray; the index of
+    // the intersection is that of the element in the object.
+    pub fn intersect(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let (object_ray, scale) = self.object_ray(ray);
+        self.object.bvh.intersect(&self.object.elements, &object_ray).map(|intersection| Intersection {
+            distance: intersection.distance / scale,
+            element: PlacedElement { instance: Some(self), ..intersection.element },
+            ..intersection
+        })
+    }
+
+    // The elements expect a unit direction; distances along the object-space
+    // ray are `scale` times those along the world-space one.
+    fn object_ray(&self, ray: &Ray) -> (Ray, f64) {
+        let direction = self.to_object.vector(&ray.direction);
+        let scale = direction.length();
+        (Ray::new(self.to_object.point(&ray.origin), direction * scale.recip()), scale)
+    }
+
+    // None if the object has unbounded elements.
+    pub fn bounding_box(&self) -> Option<BoundingBox> {
+        self.object.bvh.bounds().map(|bounds| self.transform.bounding_box(&bounds))
+    }
+}
+
+// An element as it is placed in the scene: an element of the scene itself, or
+// an element of an object along with the instance of the object that was hit.
+#[derive(Clone, Copy)]
+pub struct PlacedElement<'a> {
+    pub element: &'a Element,
+    pub instance: Option<&'a Instance>,
+}
+
+impl<'a> PlacedElement<'a> {
+
+    pub fn new(element: &'a Element) -> Self {
+        Self { element, instance: None }
+    }
+
+    pub fn material(&self) -> &'a Material {
+        self.element.material()
+    }
+
+    pub fn face_normal(&self, hit_point: &Point) -> Vector3 {
+        match self.instance {
+            Some(instance) => {
+                let normal = self.element.face_normal(&instance.to_object.point(hit_point));
+                instance.transform.normal(&normal).normalize()
+            },
+            None => self.element.face_normal(hit_point),
+        }
+    }
+}
+
+impl Intersectable for PlacedElement<'_> {
+
+    fn intersect(&self, ray: &Ray) -> Option<f64> {
+        match self.instance {
+            Some(instance) => {
+                let (object_ray, scale) = instance.object_ray(ray);
+                self.element.intersect(&object_ray).map(|distance| distance / scale)
+            },
+            None => self.element.intersect(ray),
+        }
+    }
+
+    fn surface_normal(&self, hit_point: &Point) -> Vector3 {
+        match self.instance {
+            Some(instance) => {
+                let normal = self.element.surface_normal(&instance.to_object.point(hit_point));
+                instance.transform.normal(&normal).normalize()
+            },
+            None => self.element.surface_normal(hit_point),
+        }
+    }
+
+    fn texture_coords(&self, hit_point: &Point) -> TextureCoords {
+        match self.instance {
+            Some(instance) => self.element.texture_coords(&instance.to_object.point(hit_point)),
+            None => self.element.texture_coords(hit_point),
+        }
+    }
+
+    fn bounding_box(&self) -> Option<BoundingBox> {
+        let bounds = self.element.bounding_box();
+        match self.instance {
+            Some(instance) => bounds.map(|bounds| instance.transform.bounding_box(&bounds)),
+            None => bounds,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::geometry::Matrix4;
+    use crate::raytracer::material::Color;
+
+    fn sphere(center: Point, radius: f64) -> Element {
+        Element::Sphere(Sphere { center, radius, material: Material::diffuse(Color::black(), 0.18) })
+    }
+
+    #[test]
+    fn instances_hit_like_the_transformed_element() {
+        // a unit sphere moved to z = -5 and scaled by 2, and the sphere it becomes
+        let object = Arc::new(Object::new(vec![sphere(Point::zero(), 1.0)]));
+        let matrix = Matrix4::translation(Vector3::new(0.0, 0.0, -5.0)) * Matrix4::scaling(Vector3::new(2.0, 2.0, 2.0));
+        let instance = Instance::new(object, Transform::new(matrix).unwrap());
+        let expected = sphere(Point::new(0.0, 0.0, -5.0), 2.0);
+
+        for direction in [Vector3::new(0.0, 0.0, -1.0), Vector3::new(0.2, -0.3, -1.0).normalize()] {
+            let ray = Ray::new(Point::zero(), direction);
+            let hit = instance.intersect(&ray).expect("the ray should hit the instance");
+            let distance = expected.intersect(&ray).unwrap();
+            assert!((hit.distance - distance).abs() < 1e-9);
+
+            let hit_point = ray.origin + ray.direction * distance;
+            let normal = hit.element.surface_normal(&hit_point);
+            assert!((normal - expected.surface_normal(&hit_point)).length() < 1e-9);
+            let (uv, expected_uv) = (hit.element.texture_coords(&hit_point), expected.texture_coords(&hit_point));
+            assert!((uv.x - expected_uv.x).abs() < 1e-6 && (uv.y - expected_uv.y).abs() < 1e-6);
+        }
+
+        let bounds = instance.bounding_box().unwrap();
+        assert!((bounds.min - Point::new(-2.0, -2.0, -7.0)).length() < 1e-9);
+        assert!((bounds.max - Point::new(2.0, 2.0, -3.0)).length() < 1e-9);
+    }
+
+    #[test]
+    fn instances_report_the_closest_element_of_their_object() {
+        let object = Arc::new(Object::new(vec![
+            sphere(Point::new(0.0, 0.0, -4.0), 1.0),
+            sphere(Point::new(0.0, 0.0, -2.0), 0.5),
+        ]));
+        let instance = Instance::new(object, Transform::new(Matrix4::translation(Vector3::new(0.0, 0.0, -1.0))).unwrap());
+
+        let hit = instance.intersect(&Ray::new(Point::zero(), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        assert_eq!(hit.index, 1);
+        assert!((hit.distance - 2.5).abs() < 1e-9);
+        assert!(instance.intersect(&Ray::new(Point::zero(), Vector3::new(0.0, 1.0, 0.0))).is_none());
+    }
+
+    #[test]
+    fn smooth_meshes_keep_their_face_normal_apart() {
+        // a triangle in the z = 0 plane wound towards -z, with vertex normals
+        // tilted away from +z
+        let mesh = Mesh {
+            positions: vec![Point::zero(), Point::new(0.0, 1.0, 0.0), Point::new(1.0, 0.0, 0.0)],
+            normals: vec![Vector3::new(-0.5, 0.0, 1.0).normalize(), Vector3::new(0.0, 0.0, 1.0), Vector3::new(0.5, 0.5, 1.0).normalize()],
+            uvs: Vec::new(),
+            indices: vec![[0, 1, 2]],
+            material: Material::diffuse(Color::black(), 0.18),
+        };
+        let element = mesh.into_elements().remove(0);
+        let hit_point = Point::new(0.25, 0.25, 0.0);
+
+        let face_normal = element.face_normal(&hit_point);
+        assert!((face_normal - Vector3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+        assert!((element.surface_normal(&hit_point) - face_normal).length() > 1e-3);
+        // and so does an instance of it
+        let instance = Instance::new(Arc::new(Object::new(vec![element])), Transform::new(Matrix4::translation(Vector3::new(0.0, 0.0, -2.0))).unwrap());
+        let hit = instance.intersect(&Ray::new(Point::new(0.25, 0.25, 0.0), Vector3::new(0.0, 0.0, -1.0))).unwrap();
+        let placed_normal = hit.element.face_normal(&Point::new(0.25, 0.25, -2.0));
+        assert!((placed_normal - Vector3::new(0.0, 0.0, 1.0)).length() < 1e-9);
+    }
+}
@@ -0,0 +1,201 @@
+use image::{DynamicImage, GenericImage, Rgb32FImage};
+
+use super::aov::Aov;
+use super::material::Color;
+use super::tonemap::ToneMapping;
+
+// Linear radiance of a render. Every pixel keeps the filter-weighted sum of
+// its samples, the sum of their weights and their number, so that more
+// samples can be accumulated into it later. AOVs are kept as extra layers
+// sharing the weights of the beauty image, except the unfiltered ones which
+// hold the single value written with the first samples of the pixel.
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    colors: Vec<Color>,
+    weights: Vec<f32>,
+    counts: Vec<u32>,
+    aovs: Vec<Aov>,
+    layers: Vec<Vec<Color>>,
+}
+
+impl Framebuffer {
+
+    pub fn new(width: u32, height: u32) -> Self {
+        let size = (width * height) as usize;
+        Self {
+            width,
+            height,
+            colors: vec![Color::black(); size],
+            weights: vec![0.0; size],
+            counts: vec![0; size],
+            aovs: Vec::new(),
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn with_aovs(mut self, aovs: Vec<Aov>) -> Self {
+        let size = self.colors.len();
+        self.layers = vec![vec![Color::black(); size]; aovs.len()];
+        self.aovs = aovs;
+        self
+    }
+
+    pub fn aovs(&self) -> &[Aov] {
+        &self.aovs
+    }
+
+    pub fn dimension(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.width + x) as usize
+    }
+
+    // Adds the weighted sum of `count` samples of the pixel, and of their
+    // AOVs in the order of `aovs()`.
+    pub fn accumulate(&mut self, x: u32, y: u32, color: Color, weight: f32, count: u32, aovs: &[Color]) {
+        let index = self.index(x, y);
+        let first = self.counts[index] == 0;
+        self.colors[index] = self.colors[index] + color;
+        self.weights[index] += weight;
+        self.counts[index] += count;
+        for ((aov, layer), value) in self.aovs.iter().zip(&mut self.layers).zip(aovs) {
+            if aov.is_filtered() {
+                layer[index] = layer[index] + *value;
+            } else if first {
+                layer[index] = *value;
+            }
+        }
+    }
+
+    // Adds every pixel of a framebuffer of the same dimension and AOVs.
+    pub fn merge(&mut self, other: &Framebuffer) -> Result<(), String> {
+        if self.dimension() != other.dimension() {
+            return Err(format!("cannot merge a {}x{} framebuffer into a {}x{} one",
+                other.width, other.height, self.width, self.height));
+        }
+        if self.aovs != other.aovs {
+            return Err(String::from("cannot merge framebuffers with different AOVs"));
+        }
+        for (aov, (layer, other_layer)) in self.aovs.iter().zip(self.layers.iter_mut().zip(&other.layers)) {
+            for (i, (color, other_color)) in layer.iter_mut().zip(other_layer).enumerate() {
+                if aov.is_filtered() {
+                    *color = *color + *other_color;
+                } else if self.counts[i] == 0 {
+                    *color = *other_color;
+                }
+            }
+        }
+        for (i, (color, weight)) in other.colors.iter().zip(&other.weights).enumerate() {
+            self.colors[i] = self.colors[i] + *color;
+            self.weights[i] += weight;
+            self.counts[i] += other.counts[i];
+        }
+        Ok(())
+    }
+
+    pub fn weight(&self, x: u32, y: u32) -> f32 {
+        self.weights[self.index(x, y)]
+    }
+
+    // Number of samples accumulated into the pixel.
+    pub fn count(&self, x: u32, y: u32) -> u32 {
+        self.counts[self.index(x, y)]
+    }
+
+    // Weighted average of the samples of the pixel, black without samples.
+    pub fn color(&self, x: u32, y: u32) -> Color {
+        self.average(&self.colors, x, y)
+    }
+
+    // Same for the AOV at `layer` in `aovs()`, or its value if unfiltered.
+    pub fn aov_color(&self, layer: usize, x: u32, y: u32) -> Color {
+        if self.aovs[layer].is_filtered() {
+            self.average(&self.layers[layer], x, y)
+        } else {
+            self.layers[layer][self.index(x, y)]
+        }
+    }
+
+    fn average(&self, colors: &[Color], x: u32, y: u32) -> Color {
+        let index = self.index(x, y);
+        let weight = self.weights[index];
+        // the filter weights of a few samples can also cancel out
+        if self.counts[index] == 0 || weight.abs() < 1e-6 {
+            return Color::black();
+        }
+        colors[index] * weight.recip()
+    }
+
+    fn pixels(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        (0..self.height).flat_map(move |y| (0..self.width).map(move |x| (x, y)))
+    }
+
+    // Tone mapped, sRGB-encoded 8-bit image.
+    pub fn to_image(&self, tone_mapping: ToneMapping, exposure: f32, white_point: f32) -> DynamicImage {
+        let mut image = DynamicImage::new_rgb8(self.width, self.height);
+        for (x, y) in self.pixels() {
+            image.put_pixel(x, y, tone_mapping.apply(self.color(x, y), exposure, white_point).to_rgba());
+        }
+        image
+    }
+
+    // Linear floating-point image keeping the full dynamic range.
+    pub fn to_hdr_image(&self) -> Rgb32FImage {
+        self.linear_image(|x, y| self.color(x, y))
+    }
+
+    pub fn aov_image(&self, layer: usize) -> Rgb32FImage {
+        self.linear_image(|x, y| self.aov_color(layer, x, y))
+    }
+
+    fn linear_image<F: Fn(u32, u32) -> Color>(&self, color: F) -> Rgb32FImage {
+        let mut image = Rgb32FImage::new(self.width, self.height);
+        for (x, y) in self.pixels() {
+            image.put_pixel(x, y, color(x, y).to_rgb_f32());
+        }
+        image
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rgb(color: Color) -> [f32; 3] {
+        [color.red, color.green, color.blue]
+    }
+
+    fn framebuffer() -> Framebuffer {
+        Framebuffer::new(2, 1).with_aovs(vec![Aov::Depth, Aov::Albedo])
+    }
+
+    #[test]
+    fn merging_sums_samples_and_keeps_the_first_unfiltered_values() {
+        let (red, blue) = (Color::new(1.0, 0.0, 0.0), Color::new(0.0, 0.0, 1.0));
+        let mut first = framebuffer();
+        first.accumulate(0, 0, red, 1.0, 2, &[Color::new(1.0, 1.0, 1.0), red]);
+        let mut second = framebuffer();
+        second.accumulate(0, 0, blue * 3.0, 3.0, 4, &[Color::new(2.0, 2.0, 2.0), blue * 3.0]);
+        second.accumulate(1, 0, blue, 0.5, 1, &[Color::new(5.0, 5.0, 5.0), blue]);
+
+        first.merge(&second).unwrap();
+        assert_eq!((first.weight(0, 0), first.count(0, 0)), (4.0, 6));
+        assert_eq!((first.weight(1, 0), first.count(1, 0)), (0.5, 1));
+        assert_eq!(rgb(first.color(0, 0)), rgb(Color::new(0.25, 0.0, 0.75)));
+        assert_eq!(rgb(first.aov_color(1, 0, 0)), rgb(Color::new(0.25, 0.0, 0.75)));
+        // depth comes from the samples already in the pixel, or the merged ones without any
+        assert_eq!(rgb(first.aov_color(0, 0, 0)), rgb(Color::new(1.0, 1.0, 1.0)));
+        assert_eq!(rgb(first.aov_color(0, 1, 0)), rgb(Color::new(5.0, 5.0, 5.0)));
+        assert_eq!(rgb(first.color(1, 0)), rgb(blue * 2.0));
+    }
+
+    #[test]
+    fn only_matching_framebuffers_merge() {
+        assert!(framebuffer().merge(&Framebuffer::new(1, 2).with_aovs(vec![Aov::Depth, Aov::Albedo])).is_err());
+        assert!(framebuffer().merge(&Framebuffer::new(2, 1)).is_err());
+    }
+}
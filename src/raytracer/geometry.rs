@@ -41,9 +41,9 @@ impl Sub<Point> for Point {
     }
 }
 
-impl Into<Vector3> for Point {
-    fn into(self) -> Vector3 {
-        Vector3::new(self.x, self.y, self.z)
+impl From<Point> for Vector3 {
+    fn from(point: Point) -> Vector3 {
+        Vector3::new(point.x, point.y, point.z)
     }
 }
 
@@ -137,6 +137,14 @@ impl Vector3  {
         }
     }
 
+    // Two unit vectors perpendicular to this (unit) vector and to each other.
+    pub fn orthonormal_basis(&self) -> (Vector3, Vector3) {
+        let helper = if self.x.abs() > 0.9 { Vector3::new(0.0, 1.0, 0.0) } else { Vector3::new(1.0, 0.0, 0.0) };
+        let tangent = self.cross(&helper).normalize();
+        let bitangent = self.cross(&tangent);
+        (tangent, bitangent)
+    }
+
 
 }
 
@@ -187,4 +195,361 @@ impl Neg for Vector3 {
             z: -self.z,
         }
     }
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct BoundingBox {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl BoundingBox {
+
+    pub fn new(min: Point, max: Point) -> Self {
+        Self { min, max }
+    }
+
+    pub fn empty() -> Self {
+        Self {
+            min: Point::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Point::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn union(&self, other: &BoundingBox) -> BoundingBox {
+        BoundingBox {
+            min: Point::new(self.min.x.min(other.min.x), self.min.y.min(other.min.y), self.min.z.min(other.min.z)),
+            max: Point::new(self.max.x.max(other.max.x), self.max.y.max(other.max.y), self.max.z.max(other.max.z)),
+        }
+    }
+
+    pub fn include(&self, point: &Point) -> BoundingBox {
+        BoundingBox {
+            min: Point::new(self.min.x.min(point.x), self.min.y.min(point.y), self.min.z.min(point.z)),
+            max: Point::new(self.max.x.max(point.x), self.max.y.max(point.y), self.max.z.max(point.z)),
+        }
+    }
+
+    pub fn centroid(&self) -> Point {
+        Point::new(
+            (self.min.x + self.max.x) * 0.5,
+            (self.min.y + self.max.y) * 0.5,
+            (self.min.z + self.max.z) * 0.5,
+        )
+    }
+
+    pub fn extent(&self) -> Vector3 {
+        self.max - self.min
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let (x, y, z) = self.extent().coordinate();
+        if x < 0.0 || y < 0.0 || z < 0.0 {
+            return 0.0;
+        }
+        2.0 * (x * y + y * z + z * x)
+    }
+
+    // Slab test; returns the distance at which the ray enters the box, if it
+    // does so before `max_distance`.
+    pub fn intersect(&self, origin: &Point, inverse_direction: &Vector3, max_distance: f64) -> Option<f64> {
+        let mut t_min = 0.0f64;
+        let mut t_max = max_distance;
+
+        let slabs = [
+            (self.min.x, self.max.x, origin.x, inverse_direction.x),
+            (self.min.y, self.max.y, origin.y, inverse_direction.y),
+            (self.min.z, self.max.z, origin.z, inverse_direction.z),
+        ];
+        for (min, max, origin, inverse) in slabs {
+            let t0 = (min - origin) * inverse;
+            let t1 = (max - origin) * inverse;
+            let (near, far) = if t0 < t1 { (t0, t1) } else { (t1, t0) };
+            // max/min written this way so that NaN from 0 * inf keeps the current bound
+            t_min = if near > t_min { near } else { t_min };
+            t_max = if far < t_max { far } else { t_max };
+            if t_min > t_max {
+                return None;
+            }
+        }
+        Some(t_min)
+    }
+}
+
+// Row-major 4x4 matrix acting on column vectors.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Matrix4 {
+    m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+
+    pub fn new(m: [[f64; 4]; 4]) -> Self {
+        Self { m }
+    }
+
+    pub fn identity() -> Self {
+        Self::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn translation(offset: Vector3) -> Self {
+        let mut matrix = Self::identity();
+        matrix.m[0][3] = offset.x;
+        matrix.m[1][3] = offset.y;
+        matrix.m[2][3] = offset.z;
+        matrix
+    }
+
+    pub fn scaling(factors: Vector3) -> Self {
+        let mut matrix = Self::identity();
+        matrix.m[0][0] = factors.x;
+        matrix.m[1][1] = factors.y;
+        matrix.m[2][2] = factors.z;
+        matrix
+    }
+
+    // Counter-clockwise rotations, looking down the axis towards the origin;
+    // angles in radians.
+    pub fn rotation_x(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new([
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, cos, -sin, 0.0],
+            [0.0, sin, cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_y(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new([
+            [cos, 0.0, sin, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [-sin, 0.0, cos, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn rotation_z(angle: f64) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new([
+            [cos, -sin, 0.0, 0.0],
+            [sin, cos, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ])
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut result = Self::identity();
+        for (i, row) in result.m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        result
+    }
+
+    // Gauss-Jordan elimination with partial pivoting; None if the matrix is
+    // singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inverse = Self::identity().m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap();
+            if a[pivot][column].abs() < 1e-12 {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+
+            let scale = a[column][column].recip();
+            for k in 0..4 {
+                a[column][k] *= scale;
+                inverse[column][k] *= scale;
+            }
+            for row in 0..4 {
+                if row != column {
+                    let factor = a[row][column];
+                    for k in 0..4 {
+                        a[row][k] -= factor * a[column][k];
+                        inverse[row][k] -= factor * inverse[column][k];
+                    }
+                }
+            }
+        }
+        Some(Self::new(inverse))
+    }
+
+    pub fn transform_point(&self, point: &Point) -> Point {
+        let m = &self.m;
+        let (x, y, z) = point.coordinate();
+        let w = m[3][0] * x + m[3][1] * y + m[3][2] * z + m[3][3];
+        let result = Point::new(
+            m[0][0] * x + m[0][1] * y + m[0][2] * z + m[0][3],
+            m[1][0] * x + m[1][1] * y + m[1][2] * z + m[1][3],
+            m[2][0] * x + m[2][1] * y + m[2][2] * z + m[2][3],
+        );
+        if w == 1.0 {
+            result
+        } else {
+            Point::new(result.x / w, result.y / w, result.z / w)
+        }
+    }
+
+    // Ignores the translation.
+    pub fn transform_vector(&self, vector: &Vector3) -> Vector3 {
+        let m = &self.m;
+        let (x, y, z) = vector.coordinate();
+        Vector3::new(
+            m[0][0] * x + m[0][1] * y + m[0][2] * z,
+            m[1][0] * x + m[1][1] * y + m[1][2] * z,
+            m[2][0] * x + m[2][1] * y + m[2][2] * z,
+        )
+    }
+}
+
+impl Mul<Matrix4> for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, other: Matrix4) -> Self::Output {
+        let mut result = [[0.0; 4]; 4];
+        for (i, row) in result.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * other.m[k][j]).sum();
+            }
+        }
+        Matrix4::new(result)
+    }
+}
+
+// Affine transform from object space to world space, along with its inverse
+// and the inverse transpose that normals transform by.
+#[derive(Debug, Copy, Clone)]
+pub struct Transform {
+    matrix: Matrix4,
+    inverse: Matrix4,
+    normal_matrix: Matrix4,
+}
+
+impl Transform {
+
+    // None if the matrix cannot be inverted, e.g. a zero scale.
+    pub fn new(matrix: Matrix4) -> Option<Self> {
+        matrix.inverse().map(|inverse| Self::with_inverse(matrix, inverse))
+    }
+
+    fn with_inverse(matrix: Matrix4, inverse: Matrix4) -> Self {
+        Self { matrix, inverse, normal_matrix: inverse.transpose() }
+    }
+
+    pub fn identity() -> Self {
+        Self::with_inverse(Matrix4::identity(), Matrix4::identity())
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+
+    pub fn inverse(&self) -> Transform {
+        Self::with_inverse(self.inverse, self.matrix)
+    }
+
+    pub fn point(&self, point: &Point) -> Point {
+        self.matrix.transform_point(point)
+    }
+
+    pub fn vector(&self, vector: &Vector3) -> Vector3 {
+        self.matrix.transform_vector(vector)
+    }
+
+    // Normals transform by the inverse transpose to stay perpendicular to
+    // the surface under non-uniform scaling; the result is not normalized.
+    pub fn normal(&self, normal: &Vector3) -> Vector3 {
+        self.normal_matrix.transform_vector(normal)
+    }
+
+    pub fn bounding_box(&self, bounds: &BoundingBox) -> BoundingBox {
+        let (min, max) = (bounds.min, bounds.max);
+        let mut result = BoundingBox::empty();
+        for x in [min.x, max.x] {
+            for y in [min.y, max.y] {
+                for z in [min.z, max.z] {
+                    result = result.include(&self.point(&Point::new(x, y, z)));
+                }
+            }
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_identity(matrix: &Matrix4) {
+        let identity = Matrix4::identity();
+        for i in 0..4 {
+            for j in 0..4 {
+                assert!((matrix.m[i][j] - identity.m[i][j]).abs() < 1e-9, "{:?} is not the identity", matrix);
+            }
+        }
+    }
+
+    fn transforms() -> Vec<Matrix4> {
+        vec![
+            Matrix4::identity(),
+            Matrix4::translation(Vector3::new(1.0, -2.0, 3.0)),
+            Matrix4::scaling(Vector3::new(2.0, 0.5, -3.0)),
+            Matrix4::rotation_x(0.3) * Matrix4::rotation_y(-1.2) * Matrix4::rotation_z(2.5),
+            Matrix4::translation(Vector3::new(-4.0, 0.5, 1.0))
+                * Matrix4::rotation_y(0.7)
+                * Matrix4::scaling(Vector3::new(1e-3, 10.0, 1.0)),
+            // needs pivoting: the first column starts with a zero
+            Matrix4::new([
+                [0.0, 2.0, 1.0, 0.0],
+                [1.0, 0.0, 0.0, 3.0],
+                [0.0, 1.0, 4.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0],
+            ]),
+        ]
+    }
+
+    #[test]
+    fn matrix_times_inverse_is_identity() {
+        for matrix in transforms() {
+            let inverse = matrix.inverse().expect("matrix should be invertible");
+            assert_identity(&(matrix * inverse));
+            assert_identity(&(inverse * matrix));
+        }
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        assert!(Matrix4::scaling(Vector3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Transform::new(Matrix4::new([[0.0; 4]; 4])).is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_to_transformed_surfaces() {
+        let transform = Transform::new(
+            Matrix4::rotation_z(0.4) * Matrix4::scaling(Vector3::new(3.0, 0.5, 1.0))).unwrap();
+        // a tangent and the normal of the plane x + y = 0
+        let tangent = Vector3::new(1.0, -1.0, 0.0);
+        let normal = Vector3::new(1.0, 1.0, 0.0);
+        let world_tangent = transform.vector(&tangent);
+        let world_normal = transform.normal(&normal);
+        assert!(world_tangent.dot(&world_normal).abs() < 1e-12);
+
+        let point = Point::new(1.0, 2.0, 3.0);
+        let back = transform.inverse().point(&transform.point(&point));
+        assert!((back - point).length() < 1e-12);
+    }
+}
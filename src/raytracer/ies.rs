@@ -0,0 +1,205 @@
+use std::fs;
+use std::path::Path;
+
+// IES LM-63 photometric profiles.
+//
+// Only type C photometry is supported, the one used by virtually every
+// architectural luminaire: vertical angles are measured from the nadir, which
+// is mapped onto the light direction, and horizontal angles around it. Tilt
+// data is read but ignored.
+
+pub struct IesProfile {
+    vertical_angles: Vec<f64>,   // degrees, increasing
+    horizontal_angles: Vec<f64>, // degrees, increasing
+    candela: Vec<f32>,           // relative to the maximum, one row of vertical angles per horizontal angle
+}
+
+impl IesProfile {
+
+    // Relative luminous intensity in [0, 1] in the given direction, both
+    // angles in degrees.
+    pub fn evaluate(&self, vertical: f64, horizontal: f64) -> f32 {
+        let (v, tv) = match locate(&self.vertical_angles, vertical) {
+            Some(location) => location,
+            None => return 0.0,
+        };
+        let (h, th) = locate(&self.horizontal_angles, self.fold_horizontal(horizontal))
+            .unwrap_or_else(|| (self.horizontal_angles.len() - 1, 0.0));
+
+        let columns = self.vertical_angles.len();
+        let at = |h: usize, v: usize| {
+            let h = h.min(self.horizontal_angles.len() - 1);
+            let v = v.min(columns - 1);
+            self.candela[h * columns + v]
+        };
+        let lower = at(h, v) * (1.0 - tv) + at(h, v + 1) * tv;
+        let upper = at(h + 1, v) * (1.0 - tv) + at(h + 1, v + 1) * tv;
+        lower * (1.0 - th) + upper * th
+    }
+
+    // Maps a horizontal angle onto the range covered by the profile, using
+    // the symmetry implied by its last horizontal angle.
+    fn fold_horizontal(&self, horizontal: f64) -> f64 {
+        let h = horizontal.rem_euclid(360.0);
+        let last = *self.horizontal_angles.last().unwrap();
+        if last == 0.0 {
+            0.0
+        } else if last == 90.0 {
+            let h = if h > 180.0 { 360.0 - h } else { h };
+            if h > 90.0 { 180.0 - h } else { h }
+        } else if last == 180.0 {
+            if h > 180.0 { 360.0 - h } else { h }
+        } else {
+            h
+        }
+    }
+}
+
+// Interval of `angles` containing `x` and the position of `x` within it.
+fn locate(angles: &[f64], x: f64) -> Option<(usize, f32)> {
+    let first = angles[0];
+    let last = angles[angles.len() - 1];
+    if x < first || x > last {
+        return None;
+    }
+    if angles.len() == 1 {
+        return Some((0, 0.0));
+    }
+    let i = angles.partition_point(|&angle| angle <= x).clamp(1, angles.len() - 1) - 1;
+    let span = angles[i + 1] - angles[i];
+    let t = if span > 0.0 { (x - angles[i]) / span } else { 0.0 };
+    Some((i, t.clamp(0.0, 1.0) as f32))
+}
+
+pub fn load_ies(path: &Path) -> Result<IesProfile, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("{}: could not read IES file: {}", path.display(), e))?;
+    parse_ies(&source).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+fn parse_ies(source: &str) -> Result<IesProfile, String> {
+    let mut lines = source.lines();
+    let tilt = lines
+        .by_ref()
+        .find_map(|line| line.trim().strip_prefix("TILT="))
+        .ok_or_else(|| String::from("missing TILT line"))?;
+
+    let mut numbers = lines
+        .flat_map(|line| line.split(|c: char| c.is_whitespace() || c == ','))
+        .filter(|token| !token.is_empty())
+        .map(|token| token.parse::<f64>().map_err(|_| format!("invalid number `{}`", token)));
+    let mut next = |what: &str| -> Result<f64, String> {
+        numbers.next().unwrap_or_else(|| Err(format!("unexpected end of file, missing {}", what)))
+    };
+
+    if tilt.trim() == "INCLUDE" {
+        next("lamp to luminaire geometry")?;
+        let pairs = next("number of tilt angles")? as usize;
+        for _ in 0..2 * pairs {
+            next("tilt data")?;
+        }
+    }
+
+    let _lamps = next("number of lamps")?;
+    let _lumens = next("lumens per lamp")?;
+    let multiplier = next("candela multiplier")?;
+    let vertical_count = next("number of vertical angles")? as usize;
+    let horizontal_count = next("number of horizontal angles")? as usize;
+    let photometric_type = next("photometric type")?;
+    for what in ["units type", "width", "length", "height", "ballast factor", "ballast lamp factor", "input watts"] {
+        next(what)?;
+    }
+
+    if photometric_type != 1.0 {
+        return Err(format!("only type C photometry is supported, got type {}", photometric_type));
+    }
+    if vertical_count == 0 || horizontal_count == 0 {
+        return Err(String::from("profile needs at least one vertical and one horizontal angle"));
+    }
+
+    let vertical_angles = (0..vertical_count).map(|_| next("vertical angle")).collect::<Result<Vec<_>, _>>()?;
+    let horizontal_angles = (0..horizontal_count).map(|_| next("horizontal angle")).collect::<Result<Vec<_>, _>>()?;
+    let candela = (0..vertical_count * horizontal_count)
+        .map(|_| next("candela value").map(|value| (value * multiplier) as f32))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    if vertical_angles.windows(2).any(|pair| pair[0] > pair[1]) || horizontal_angles.windows(2).any(|pair| pair[0] > pair[1]) {
+        return Err(String::from("angles must be increasing"));
+    }
+
+    let max = candela.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return Err(String::from("profile emits no light"));
+    }
+
+    Ok(IesProfile {
+        vertical_angles,
+        horizontal_angles,
+        candela: candela.iter().map(|value| value / max).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Header numbers up to the angles: one lamp, 1000 lumens, the candela
+    // multiplier, the angle counts, type C, then units, size and ballast.
+    fn profile(multiplier: f64, vertical: &[f64], horizontal: &[f64], candela: &[f64]) -> String {
+        let list = |values: &[f64]| values.iter().map(f64::to_string).collect::<Vec<_>>().join(" ");
+        format!("IESNA:LM-63-2002\n[TEST] test\nTILT=NONE\n1 1000 {} {} {} 1 1 0 0 0\n1 1 100\n{}\n{}\n{}\n",
+            multiplier, vertical.len(), horizontal.len(), list(vertical), list(horizontal), list(candela))
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-6
+    }
+
+    #[test]
+    fn interpolates_vertical_angles() {
+        let profile = parse_ies(&profile(2.0, &[0.0, 45.0, 90.0], &[0.0], &[100.0, 50.0, 0.0])).unwrap();
+        assert!(close(profile.evaluate(0.0, 0.0), 1.0));
+        assert!(close(profile.evaluate(22.5, 123.0), 0.75));
+        assert!(close(profile.evaluate(90.0, 0.0), 0.0));
+        // beyond the last angle the luminaire emits nothing
+        assert!(close(profile.evaluate(120.0, 0.0), 0.0));
+    }
+
+    #[test]
+    fn folds_horizontal_angles_by_symmetry() {
+        // quadrant symmetric: bright at 0 degrees, dark at 90
+        let profile = parse_ies(&profile(1.0, &[0.0, 90.0], &[0.0, 90.0], &[10.0, 10.0, 0.0, 0.0])).unwrap();
+        assert!(close(profile.evaluate(0.0, 0.0), 1.0));
+        assert!(close(profile.evaluate(0.0, 45.0), 0.5));
+        assert!(close(profile.evaluate(0.0, 180.0), 1.0));
+        assert!(close(profile.evaluate(0.0, 270.0), 0.0));
+        assert!(close(profile.evaluate(0.0, 315.0), 0.5));
+    }
+
+    #[test]
+    fn skips_included_tilt_data() {
+        let source = profile(1.0, &[0.0, 90.0], &[0.0], &[1.0, 1.0])
+            .replace("TILT=NONE\n", "TILT=INCLUDE\n1\n2\n0 90\n1 1\n");
+        let profile = parse_ies(&source).unwrap();
+        assert!(close(profile.evaluate(45.0, 0.0), 1.0));
+    }
+
+    fn parse_error(source: &str) -> String {
+        match parse_ies(source) {
+            Ok(_) => panic!("`{}` should not parse", source),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_profiles() {
+        let valid = profile(1.0, &[0.0, 90.0], &[0.0], &[1.0, 1.0]);
+        assert_eq!(parse_error(&valid.replace("TILT=NONE", "")), "missing TILT line");
+        assert_eq!(parse_error(&valid.replace("1 1000", "1 x")), "invalid number `x`");
+        assert_eq!(parse_error(&valid[..valid.len() - 4]), "unexpected end of file, missing candela value");
+        assert_eq!(parse_error(&valid.replace("1 1000 1 2 1 1", "1 1000 1 2 1 2")),
+            "only type C photometry is supported, got type 2");
+        assert_eq!(parse_error(&profile(1.0, &[90.0, 0.0], &[0.0], &[1.0, 1.0])), "angles must be increasing");
+        assert_eq!(parse_error(&profile(1.0, &[0.0, 90.0], &[0.0], &[0.0, 0.0])), "profile emits no light");
+    }
+}
@@ -0,0 +1,123 @@
+use rand::Rng;
+
+use super::geometry::Vector3;
+use super::material::{Color, SurfaceType, transmittance};
+use super::microfacet::Microfacet;
+use super::ray::Ray;
+use super::scene::{Scene, ShadingPoint, direct_lighting, fresnel, highlights, shading_point};
+
+// Bounces after which paths may be terminated by russian roulette.
+const MIN_BOUNCES: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Integrator {
+    Whitted,     // direct lighting plus perfect reflection and refraction
+    PathTracing, // Monte Carlo global illumination
+}
+
+// Unidirectional path tracer. Diffuse bounces are importance sampled with a
+// cosine-weighted hemisphere and lights are sampled explicitly at every
+// diffuse vertex (next-event estimation); lights are not part of the scene
+// geometry, so paths can never hit them by chance. Reflective and
+// refractive surfaces choose one lobe at random.
+pub fn trace_path<R: Rng>(scene: &Scene, ray: &Ray, rng: &mut R) -> Color {
+    let mut radiance = Color::black();
+    let mut throughput = Color::new(1.0, 1.0, 1.0);
+    let mut ray = Ray::new(ray.origin, ray.direction).with_cone(ray.cone).with_medium(ray.in_medium);
+
+    for bounce in 0..scene.max_recursion_depth {
+        let intersection = match scene.trace(&ray) {
+            Some(intersection) => intersection,
+            None => break,
+        };
+        let point = shading_point(&ray, &intersection);
+        let ShadingPoint { hit_point, normal: facing_normal, geometric_normal: facing_geometric_normal, view, inside, .. } = point;
+        let (surface_normal, geometric_normal) = point.outward_normals();
+        // specular bounces carry on the cone of the incoming ray, diffuse
+        // and glossy ones start a plain ray
+        let cone = ray.cone.at(intersection.distance);
+
+        // absorption along the segment travelled inside a refractive material
+        if let SurfaceType::Refractive { absorption, .. } = point.material.surface {
+            if inside && ray.in_medium {
+                throughput = throughput * transmittance(point.material.color, absorption, intersection.distance);
+            }
+        }
+
+        // highlights are not part of any lobe and are added at every vertex
+        radiance = radiance + throughput * highlights(scene, &point, rng);
+
+        let diffuse_probability = match point.material.surface {
+            SurfaceType::Diffuse => 1.0,
+            SurfaceType::Reflective { reflectivity } => 1.0 - reflectivity,
+            SurfaceType::Refractive { transparency, .. } => 1.0 - transparency,
+            SurfaceType::Microfacet { .. } => 0.0, // sampled by its own lobes below
+        };
+
+        if let SurfaceType::Microfacet { roughness, metallic } = point.material.surface {
+            radiance = radiance + throughput * direct_lighting(scene, &point, rng);
+
+            let surface = Microfacet {
+                color: point.material.color,
+                albedo: point.material.albedo,
+                roughness,
+                metallic,
+            };
+            let specular_probability = surface.specular_probability(&facing_normal, &view);
+            let direction = if rng.random::<f32>() < specular_probability {
+                match surface.sample_specular(&facing_normal, &view, rng) {
+                    Some((direction, weight)) => {
+                        throughput = throughput * weight * specular_probability.recip();
+                        direction
+                    },
+                    None => break,
+                }
+            } else {
+                throughput = throughput * surface.diffuse_weight(&facing_normal, &view) * (1.0 - specular_probability).recip();
+                cosine_sample_hemisphere(facing_normal, rng)
+            };
+            ray = Ray::new(hit_point + facing_geometric_normal * scene.shadow_bias, direction);
+        } else if rng.random::<f32>() < diffuse_probability {
+            radiance = radiance + throughput * direct_lighting(scene, &point, rng);
+
+            // the cosine term and the lambertian 1/pi cancel with the sampling pdf
+            throughput = throughput * point.material.color * point.material.albedo;
+            ray = Ray::new(hit_point + facing_geometric_normal * scene.shadow_bias, cosine_sample_hemisphere(facing_normal, rng));
+        } else {
+            match point.material.surface {
+                SurfaceType::Refractive { index, .. } => {
+                    let kr = fresnel(ray.direction, surface_normal, index) as f32;
+                    let transmission_ray = if rng.random::<f32>() < kr {
+                        None
+                    } else {
+                        Ray::create_transmission(surface_normal, geometric_normal, ray.direction, hit_point, scene.shadow_bias, index)
+                    };
+                    ray = transmission_ray.unwrap_or_else(||
+                        Ray::create_reflection(facing_normal, facing_geometric_normal, ray.direction, hit_point, scene.shadow_bias)
+                            .with_medium(ray.in_medium)).with_cone(cone);
+                },
+                _ => {
+                    ray = Ray::create_reflection(facing_normal, facing_geometric_normal, ray.direction, hit_point, scene.shadow_bias).with_cone(cone);
+                },
+            }
+        }
+
+        if bounce >= MIN_BOUNCES {
+            let survival = throughput.red.max(throughput.green).max(throughput.blue).clamp(0.05, 0.95);
+            if rng.random::<f32>() > survival {
+                break;
+            }
+            throughput = throughput * survival.recip();
+        }
+    }
+
+    radiance
+}
+
+fn cosine_sample_hemisphere<R: Rng>(normal: Vector3, rng: &mut R) -> Vector3 {
+    let (tangent, bitangent) = normal.orthonormal_basis();
+    let r = rng.random::<f64>().sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.random::<f64>();
+    let z = (1.0 - r * r).max(0.0).sqrt();
+    (tangent * (r * phi.cos()) + bitangent * (r * phi.sin()) + normal * z).normalize()
+}
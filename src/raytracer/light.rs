@@ -1,17 +1,35 @@
-use super::{material::Color, geometry::{Point, Vector3}};
+use std::f32::consts::PI;
+
+use super::{material::Color, geometry::{Point, Vector3}, ies::IesProfile, sampling::concentric_disk};
 
 
 pub enum Light {
     DirectionalLight(DirectionalLight),
-    SphericalLight(SphericalLight)
+    SphericalLight(SphericalLight),
+    SpotLight(SpotLight),
+    RectangleLight(RectangleLight),
+    DiskLight(DiskLight),
+    SphereLight(SphereLight)
 }
 
+// A point on a light as seen from a hit point.
+pub struct LightSample {
+    pub direction: Vector3,
+    pub distance: f64,
+    pub intensity: f32,
+}
+
+// For area lights `intensity`, `distance` and `direction_from` treat the light
+// as a point at its center; `sample` picks a point on its surface.
 impl  Light {
     pub fn color(&self) -> Color {
         match self {
             Light::SphericalLight(s) => s.color,
             Light::DirectionalLight(s) => s.color,
-
+            Light::SpotLight(s) => s.color,
+            Light::RectangleLight(r) => r.color,
+            Light::DiskLight(d) => d.color,
+            Light::SphereLight(s) => s.color,
         }
     }
 
@@ -19,6 +37,10 @@ impl  Light {
         match self {
             Light::SphericalLight(s) => s.intensity(hit_point),
             Light::DirectionalLight(s) => s.intensity,
+            Light::SpotLight(s) => s.intensity(hit_point),
+            Light::RectangleLight(r) => r.sample(hit_point, (0.5, 0.5)).intensity,
+            Light::DiskLight(d) => d.sample(hit_point, (0.5, 0.5)).intensity,
+            Light::SphereLight(s) => s.point_intensity(hit_point),
         }
     }
 
@@ -26,6 +48,10 @@ impl  Light {
         match self {
             Light::SphericalLight(s) => s.distance(hit_point),
             Light::DirectionalLight(s) => s.distance(hit_point),
+            Light::SpotLight(s) => (s.position - *hit_point).length(),
+            Light::RectangleLight(r) => (r.position - *hit_point).length(),
+            Light::DiskLight(d) => (d.position - *hit_point).length(),
+            Light::SphereLight(s) => ((s.position - *hit_point).length() - s.radius).max(0.0),
         }
     }
 
@@ -33,6 +59,34 @@ impl  Light {
         match self {
             Light::SphericalLight(s) => s.direction_from(hit_point),
             Light::DirectionalLight(s) => s.direction_from(hit_point),
+            Light::SpotLight(s) => (s.position - *hit_point).normalize(),
+            Light::RectangleLight(r) => (r.position - *hit_point).normalize(),
+            Light::DiskLight(d) => (d.position - *hit_point).normalize(),
+            Light::SphereLight(s) => (s.position - *hit_point).normalize(),
+        }
+    }
+
+    // Number of shadow rays to cast per shading point.
+    pub fn sample_count(&self) -> u32 {
+        match self {
+            Light::SphericalLight(_) | Light::DirectionalLight(_) | Light::SpotLight(_) => 1,
+            Light::RectangleLight(r) => r.samples.max(1),
+            Light::DiskLight(d) => d.samples.max(1),
+            Light::SphereLight(s) => s.samples.max(1),
+        }
+    }
+
+    // `u` is a point of the unit square mapped onto the light surface.
+    pub fn sample(&self, hit_point: &Point, u: (f64, f64)) -> LightSample {
+        match self {
+            Light::RectangleLight(r) => r.sample(hit_point, u),
+            Light::DiskLight(d) => d.sample(hit_point, u),
+            Light::SphereLight(s) => s.sample(hit_point, u),
+            _ => LightSample {
+                direction: self.direction_from(hit_point),
+                distance: self.distance(hit_point),
+                intensity: self.intensity(hit_point),
+            },
         }
     }
 
@@ -51,7 +105,7 @@ impl DirectionalLight {
     }
 
     fn distance(&self, _: &Point) -> f64 {
-        std::f64::MAX
+        f64::MAX
     }
 
     fn direction_from(&self, _: &Point) -> Vector3 {
@@ -82,6 +136,262 @@ impl SphericalLight {
 
     fn intensity(&self, hit_point: &Point) -> f32 {
         let r2 = (self.position - *hit_point).length().powi(2) as f32;
-        self.intensity / (4.0 * ::std::f32::consts::PI * r2)
+        self.intensity / (4.0 * PI * r2)
+    }
+}
+
+
+// Point light shining in a cone around `direction`. The intensity is full
+// within `inner_angle` of the axis and falls off smoothly to zero at
+// `outer_angle`, both half-angles in degrees. An IES profile, if any, further
+// shapes the light inside the cone.
+pub struct SpotLight {
+    pub position: Point,
+    pub direction: Vector3,
+    pub inner_angle: f64,
+    pub outer_angle: f64,
+    pub color: Color,
+    pub intensity: f32,
+    pub profile: Option<IesProfile>,
+}
+
+impl SpotLight {
+    pub fn new(position: Point, direction: Vector3, inner_angle: f64, outer_angle: f64, color: Color, intensity: f32) -> Self {
+        Self { position, direction, inner_angle, outer_angle, color, intensity, profile: None }
+    }
+
+    pub fn with_profile(mut self, profile: IesProfile) -> Self {
+        self.profile = Some(profile);
+        self
+    }
+
+    fn intensity(&self, hit_point: &Point) -> f32 {
+        let to_point = *hit_point - self.position;
+        let r2 = to_point.length().powi(2) as f32;
+        self.intensity * self.falloff(&to_point.normalize()) / (4.0 * PI * r2)
+    }
+
+    // Relative intensity in the given direction away from the light.
+    fn falloff(&self, direction: &Vector3) -> f32 {
+        let axis = self.direction.normalize();
+        let cos_theta = axis.dot(direction);
+        let cos_inner = self.inner_angle.to_radians().cos();
+        let cos_outer = self.outer_angle.to_radians().cos();
+
+        let cone = if cos_theta >= cos_inner {
+            1.0
+        } else if cos_theta <= cos_outer {
+            0.0
+        } else {
+            let t = (cos_theta - cos_outer) / (cos_inner - cos_outer);
+            t * t * (3.0 - 2.0 * t)
+        };
+
+        match &self.profile {
+            Some(profile) if cone > 0.0 => {
+                let (tangent, bitangent) = axis.orthonormal_basis();
+                let vertical = cos_theta.clamp(-1.0, 1.0).acos().to_degrees();
+                let horizontal = direction.dot(&bitangent).atan2(direction.dot(&tangent)).to_degrees();
+                cone as f32 * profile.evaluate(vertical, horizontal)
+            },
+            _ => cone as f32,
+        }
+    }
+}
+
+// Irradiance at normal incidence from a point of a one-sided lambertian
+// emitter of total power `power`, sampled uniformly over its area.
+fn emitter_sample(power: f32, emitter_normal: &Vector3, hit_point: &Point, light_point: Point) -> LightSample {
+    let to_light = light_point - *hit_point;
+    let distance = to_light.length();
+    if distance == 0.0 {
+        // A hit point on the emitter itself sees it edge-on.
+        return LightSample { direction: *emitter_normal, distance, intensity: 0.0 };
+    }
+    let direction = to_light.normalize();
+    let cos_light = (-emitter_normal.dot(&direction)).max(0.0) as f32;
+    LightSample {
+        direction,
+        distance,
+        intensity: power * cos_light / (PI * (distance * distance) as f32),
+    }
+}
+
+
+// Rectangle centered on `position` spanned by two edges, emitting on the side
+// of edge_u x edge_v.
+pub struct RectangleLight {
+    pub position: Point,
+    pub edge_u: Vector3,
+    pub edge_v: Vector3,
+    pub color: Color,
+    pub intensity: f32,
+    pub samples: u32,
+}
+
+impl RectangleLight {
+    pub fn new(position: Point, edge_u: Vector3, edge_v: Vector3, color: Color, intensity: f32, samples: u32) -> Self {
+        Self { position, edge_u, edge_v, color, intensity, samples }
+    }
+
+    fn sample(&self, hit_point: &Point, (u, v): (f64, f64)) -> LightSample {
+        let normal = self.edge_u.cross(&self.edge_v).normalize();
+        let light_point = self.position + self.edge_u * (u - 0.5) + self.edge_v * (v - 0.5);
+        emitter_sample(self.intensity, &normal, hit_point, light_point)
+    }
+}
+
+
+// Disk emitting on the side its normal points to.
+pub struct DiskLight {
+    pub position: Point,
+    pub normal: Vector3,
+    pub radius: f64,
+    pub color: Color,
+    pub intensity: f32,
+    pub samples: u32,
+}
+
+impl DiskLight {
+    pub fn new(position: Point, normal: Vector3, radius: f64, color: Color, intensity: f32, samples: u32) -> Self {
+        Self { position, normal, radius, color, intensity, samples }
+    }
+
+    fn sample(&self, hit_point: &Point, u: (f64, f64)) -> LightSample {
+        let normal = self.normal.normalize();
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let (x, y) = concentric_disk(u);
+        let light_point = self.position + tangent * (x * self.radius) + bitangent * (y * self.radius);
+        emitter_sample(self.intensity, &normal, hit_point, light_point)
+    }
+}
+
+
+// Spherical area light. Unlike `SphericalLight` it has a size, so its shadows
+// have a penumbra.
+pub struct SphereLight {
+    pub position: Point,
+    pub radius: f64,
+    pub color: Color,
+    pub intensity: f32,
+    pub samples: u32,
+}
+
+impl SphereLight {
+    pub fn new(position: Point, radius: f64, color: Color, intensity: f32, samples: u32) -> Self {
+        Self { position, radius, color, intensity, samples }
+    }
+
+    fn point_intensity(&self, hit_point: &Point) -> f32 {
+        let r2 = (self.position - *hit_point).length().powi(2) as f32;
+        self.intensity / (4.0 * PI * r2)
+    }
+
+    // Samples the cone of directions subtended by the sphere, over which its
+    // radiance is constant.
+    fn sample(&self, hit_point: &Point, (u, v): (f64, f64)) -> LightSample {
+        let to_center = self.position - *hit_point;
+        let center_distance = to_center.length();
+        if center_distance < self.radius {
+            // Inside the sphere radiance comes from every direction: sample
+            // them uniformly and shade up to the surface in front.
+            let cos_theta = 1.0 - 2.0 * u;
+            let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+            let phi = 2.0 * std::f64::consts::PI * v;
+            let direction = Vector3::new(sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta);
+            let b = to_center.dot(&direction);
+            let distance = b + (self.radius * self.radius - center_distance * center_distance + b * b).max(0.0).sqrt();
+
+            // radiance P / (4 pi^2 R^2) over the pdf 1 / (4 pi)
+            return LightSample {
+                direction,
+                distance,
+                intensity: self.intensity / (PI * (self.radius * self.radius) as f32),
+            };
+        }
+
+        let w = to_center.normalize();
+        let (tangent, bitangent) = w.orthonormal_basis();
+        let sin_max = self.radius / center_distance;
+        let cos_max = (1.0 - sin_max * sin_max).max(0.0).sqrt();
+        let cos_theta = 1.0 - u * (1.0 - cos_max);
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * v;
+        let direction = (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + w * cos_theta).normalize();
+
+        let b = center_distance * cos_theta;
+        let distance = b - (self.radius * self.radius - center_distance * center_distance * sin_theta * sin_theta).max(0.0).sqrt();
+
+        // radiance P / (4 pi^2 R^2) times the solid angle 2 pi (1 - cos_max)
+        LightSample {
+            direction,
+            distance,
+            intensity: self.intensity * (1.0 - cos_max) as f32 / (2.0 * PI * (self.radius * self.radius) as f32),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Irradiance on a surface at `hit_point` facing `normal`, averaged over a
+    // grid of samples on the light.
+    fn irradiance(light: &Light, hit_point: &Point, normal: &Vector3) -> f32 {
+        let n = 64;
+        let mut total = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = ((i as f64 + 0.5) / n as f64, (j as f64 + 0.5) / n as f64);
+                let sample = light.sample(hit_point, u);
+                assert!(sample.distance > 0.0);
+                total += (normal.dot(&sample.direction) as f32).max(0.0) * sample.intensity;
+            }
+        }
+        total / (n * n) as f32
+    }
+
+    fn assert_close(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() <= 1e-3 * expected, "{actual} != {expected}");
+    }
+
+    #[test]
+    fn area_lights_look_like_point_lights_from_far_away() {
+        let white = Color::new(1.0, 1.0, 1.0);
+        let (hit_point, normal) = (Point::zero(), Vector3::new(0.0, 0.0, 1.0));
+        let position = Point::new(0.0, 0.0, 1000.0);
+
+        let sphere = Light::SphereLight(SphereLight::new(position, 1.0, white, 100.0, 16));
+        let point = Light::SphericalLight(SphericalLight::new(position, white, 100.0));
+        assert_close(irradiance(&sphere, &hit_point, &normal), irradiance(&point, &hit_point, &normal));
+
+        // A one-sided emitter sends 4 times the power of a point light towards its front.
+        let edge_u = Vector3::new(1.0, 0.0, 0.0);
+        let edge_v = Vector3::new(0.0, -1.0, 0.0);
+        let rectangle = Light::RectangleLight(RectangleLight::new(position, edge_u, edge_v, white, 100.0, 16));
+        let point = Light::SphericalLight(SphericalLight::new(position, white, 400.0));
+        assert_close(irradiance(&rectangle, &hit_point, &normal), irradiance(&point, &hit_point, &normal));
+    }
+
+    #[test]
+    fn points_inside_sphere_lights_are_lit() {
+        let light = Light::SphereLight(SphereLight::new(Point::zero(), 2.0, Color::new(1.0, 1.0, 1.0), 100.0, 16));
+        let expected = 100.0 / (4.0 * PI * 4.0);
+        for hit_point in [Point::zero(), Point::new(0.5, -1.0, 0.3)] {
+            for normal in [Vector3::new(0.0, 1.0, 0.0), Vector3::new(1.0, 1.0, -1.0).normalize()] {
+                let sample = light.sample(&hit_point, (0.3, 0.7));
+                assert!(((hit_point + sample.direction * sample.distance - Point::zero()).length() - 2.0).abs() < 1e-9);
+                assert!((irradiance(&light, &hit_point, &normal) - expected).abs() < 1e-2 * expected);
+            }
+        }
+    }
+
+    #[test]
+    fn points_on_rectangle_lights_get_no_light_from_them() {
+        let light = RectangleLight::new(Point::zero(), Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0), Color::new(1.0, 1.0, 1.0), 100.0, 1);
+        let sample = light.sample(&Point::zero(), (0.5, 0.5));
+        assert_eq!(sample.intensity, 0.0);
+        assert!(sample.direction.length().is_finite());
     }
 }
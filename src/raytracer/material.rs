@@ -1,15 +1,27 @@
 use std::ops::{Mul, Add};
+use std::sync::Arc;
 
-use image::{Rgba, DynamicImage, GenericImageView, Pixel};
+use image::{Rgb, Rgba, DynamicImage, GenericImageView, Pixel};
 
-const GAMMA: f32 = 2.2;
+use super::geometry::{Point, Vector3};
+use super::bump::{BumpMap, NormalMap};
+use super::procedural::Procedural;
 
-fn gamma_encode(linear: f32) -> f32 {
-    linear.powf(1.0 / GAMMA)
+// sRGB transfer curve: linear segment near black, 2.4 power above.
+fn srgb_encode(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
 }
 
-fn gamma_decode(encoded: f32) -> f32 {
-     encoded.powf(GAMMA)
+fn srgb_decode(encoded: f32) -> f32 {
+    if encoded <= 0.04045 {
+        encoded / 12.92
+    } else {
+        ((encoded + 0.055) / 1.055).powf(2.4)
+    }
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -34,29 +46,48 @@ impl Color {
         Self { red, green, blue }
     }
 
+    pub fn luminance(&self) -> f32 {
+        0.2126 * self.red + 0.7152 * self.green + 0.0722 * self.blue
+    }
+
     pub fn clamp(&self) -> Color {
         Color {
-            red: self.red.min(1.0).max(0.0),
-            blue: self.blue.min(1.0).max(0.0),
-            green: self.green.min(1.0).max(0.0),
+            red: self.red.clamp(0.0, 1.0),
+            blue: self.blue.clamp(0.0, 1.0),
+            green: self.green.clamp(0.0, 1.0),
         }
     }
 
+    // sRGB-encoded 8-bit color; values outside [0, 1] are clipped.
     pub fn to_rgba(&self) -> Rgba<u8> {
         Rgba([
-            (gamma_encode(self.red) * 255.0) as u8,
-            (gamma_encode(self.green) * 255.0) as u8,
-            (gamma_encode(self.blue) * 255.0) as u8,
+            (srgb_encode(self.red) * 255.0).round() as u8,
+            (srgb_encode(self.green) * 255.0).round() as u8,
+            (srgb_encode(self.blue) * 255.0).round() as u8,
             255,
         ])
     }
 
+    // Linear values, unclamped, for floating-point outputs.
+    pub fn to_rgb_f32(&self) -> Rgb<f32> {
+        Rgb([self.red, self.green, self.blue])
+    }
+
     pub fn from_rgba(rgba: Rgba<u8>) -> Color {
         let channels = rgba.channels();
         Color {
-            red: gamma_decode((channels[0] as f32) / 255.0),
-            green: gamma_decode((channels[1] as f32) / 255.0),
-            blue : gamma_decode((channels[2] as f32) / 255.0),
+            red: srgb_decode((channels[0] as f32) / 255.0),
+            green: srgb_decode((channels[1] as f32) / 255.0),
+            blue : srgb_decode((channels[2] as f32) / 255.0),
+        }
+    }
+
+    pub fn from_rgba_linear(rgba: Rgba<u8>) -> Color {
+        let channels = rgba.channels();
+        Color {
+            red: (channels[0] as f32) / 255.0,
+            green: (channels[1] as f32) / 255.0,
+            blue: (channels[2] as f32) / 255.0,
         }
     }
 }
@@ -106,63 +137,490 @@ impl Add<Color> for Color {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum SurfaceType {
     Diffuse,
     Reflective { reflectivity: f32 },
-    Refractive { index: f32, transparency: f32 },
+    // Dielectric such as glass or water. Light travelling through it is tinted
+    // to the surface color over 1 / absorption units of distance; the opaque
+    // remainder (1 - transparency) is shaded as a diffuse surface.
+    Refractive { index: f32, transparency: f32, absorption: f32 },
+    // Glossy surface, from mirror-like (roughness 0) to matte (roughness 1),
+    // dielectric (metallic 0) or metal (metallic 1).
+    Microfacet { roughness: f32, metallic: f32 },
+}
+
+// Refractive materials reach their surface color after one unit of distance
+// unless told otherwise.
+pub const DEFAULT_ABSORPTION: f32 = 1.0;
+
+// Beer–Lambert transmittance over `distance` inside a refractive material.
+pub fn transmittance(color: Color, absorption: f32, distance: f64) -> Color {
+    let exponent = absorption * distance as f32;
+    Color::new(color.red.powf(exponent), color.green.powf(exponent), color.blue.powf(exponent))
 }
 
 
 pub struct Material {
     pub coloration: Coloration,
     pub albedo: f32,
-    pub surface: SurfaceType
+    pub surface: SurfaceType,
+    // Blinn–Phong highlight of the lights on every surface type but
+    // microfacet; black for none.
+    pub specular_color: Color,
+    pub specular_exponent: f32,
+    // Detail of the shading normal; the bump map applies on top of the
+    // normal map.
+    pub normal_map: Option<NormalMap>,
+    pub bump_map: Option<BumpMap>,
+    // Scale the albedo and the parameters of the surface type over the
+    // surface.
+    pub parameter_maps: Vec<ParameterMap>,
+}
+
+// Scalar parameters of a material that can be read from a map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    Albedo,
+    Reflectivity,
+    Transparency,
+    Index,
+    Roughness,
+    Metallic,
+}
+
+// Channel of a coloration holding a scalar, for maps packing several
+// parameters in one image.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Luminance,
+}
+
+// The map value multiplies the constant of the parameter, except for the
+// index of refraction, which goes from 1 for a value of 0 to the constant
+// for a value of 1.
+pub struct ParameterMap {
+    pub parameter: Parameter,
+    pub coloration: Coloration,
+    pub channel: Channel,
+}
+
+// A material at one surface point, with every map applied.
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialSample {
+    pub color: Color,
+    pub albedo: f32,
+    pub surface: SurfaceType,
+}
+
+impl Material {
+
+    // Plain lambertian material without highlight or maps.
+    pub fn diffuse(color: Color, albedo: f32) -> Material {
+        Material {
+            coloration: Coloration::Color(color),
+            albedo,
+            surface: SurfaceType::Diffuse,
+            specular_color: Color::black(),
+            specular_exponent: 0.0,
+            normal_map: None,
+            bump_map: None,
+            parameter_maps: Vec::new(),
+        }
+    }
+
+    // Whether any part of the material varies over the surface, so that its
+    // lookups need a footprint.
+    pub fn has_maps(&self) -> bool {
+        !self.coloration.is_constant() || self.normal_map.is_some() || self.bump_map.is_some() || !self.parameter_maps.is_empty()
+    }
+
+    // Maps are filtered over `footprint` uv units around the coordinates.
+    pub fn sample(&self, texture_coord: &TextureCoords, position: &Point, footprint: f32) -> MaterialSample {
+        let mut sample = MaterialSample {
+            color: self.coloration.sample(texture_coord, position, footprint),
+            albedo: self.albedo,
+            surface: self.surface,
+        };
+        for map in &self.parameter_maps {
+            let color = map.coloration.sample(texture_coord, position, footprint);
+            let value = match map.channel {
+                Channel::Red => color.red,
+                Channel::Green => color.green,
+                Channel::Blue => color.blue,
+                Channel::Luminance => color.luminance(),
+            };
+            match (map.parameter, &mut sample.surface) {
+                (Parameter::Albedo, _) => sample.albedo *= value,
+                (Parameter::Reflectivity, SurfaceType::Reflective { reflectivity }) => *reflectivity *= value,
+                (Parameter::Transparency, SurfaceType::Refractive { transparency, .. }) => *transparency *= value,
+                (Parameter::Index, SurfaceType::Refractive { index, .. }) => *index = 1.0 + (*index - 1.0) * value,
+                (Parameter::Roughness, SurfaceType::Microfacet { roughness, .. }) => *roughness *= value,
+                (Parameter::Metallic, SurfaceType::Microfacet { metallic, .. }) => *metallic *= value,
+                // the surface type has no such parameter
+                _ => {},
+            }
+        }
+        sample
+    }
+
+    pub fn has_highlight(&self) -> bool {
+        let color = self.specular_color;
+        color.red > 0.0 || color.green > 0.0 || color.blue > 0.0
+    }
+
+    // Normalized Blinn–Phong BRDF, the highlight getting smaller but brighter
+    // as the exponent grows.
+    pub fn highlight(&self, normal: &Vector3, view: &Vector3, light: &Vector3) -> Color {
+        if normal.dot(view) <= 0.0 || normal.dot(light) <= 0.0 {
+            return Color::black();
+        }
+        let half = (*view + *light).normalize();
+        let exponent = self.specular_exponent;
+        let normalization = (exponent + 8.0) / (8.0 * std::f32::consts::PI);
+        let n_dot_h = (normal.dot(&half) as f32).max(0.0);
+        self.specular_color * (normalization * n_dot_h.powf(exponent))
+    }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct TextureCoords {
     pub x: f32,
     pub y: f32,
 }
 
+// How a texture is sampled between and across texels. Trilinear filtering
+// blends the two mip levels closest to the footprint of the lookup.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+// What a texture lookup returns outside [0, 1). The border color is linear.
+#[derive(Debug, Clone, Copy)]
+pub enum AddressMode {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    Border(Color),
+}
+
+impl AddressMode {
+
+    // Index of the texel at `coord` along an axis of `size` texels, None
+    // outside a texture with a border.
+    fn texel_index(&self, coord: i64, size: u32) -> Option<u32> {
+        let size = size as i64;
+        let index = match self {
+            AddressMode::Repeat => coord.rem_euclid(size),
+            AddressMode::MirroredRepeat => {
+                let coord = coord.rem_euclid(2 * size);
+                if coord < size { coord } else { 2 * size - 1 - coord }
+            },
+            AddressMode::ClampToEdge => coord.clamp(0, size - 1),
+            AddressMode::Border(_) => (0..size).contains(&coord).then_some(coord)?,
+        };
+        Some(index as u32)
+    }
+}
+
+// Scale, rotation and offset applied to texture coordinates, in that order,
+// before a lookup. The rotation is in radians.
+#[derive(Debug, Clone, Copy)]
+pub struct UvTransform {
+    pub scale: [f32; 2],
+    pub rotation: f32,
+    pub offset: [f32; 2],
+}
+
+impl UvTransform {
+
+    pub fn apply(&self, texture_coord: &TextureCoords) -> TextureCoords {
+        let x = texture_coord.x * self.scale[0];
+        let y = texture_coord.y * self.scale[1];
+        let (sin, cos) = self.rotation.sin_cos();
+        TextureCoords {
+            x: x * cos - y * sin + self.offset[0],
+            y: x * sin + y * cos + self.offset[1],
+        }
+    }
+
+    // How much a footprint around the coordinates grows once transformed.
+    fn footprint_scale(&self) -> f32 {
+        (self.scale[0] * self.scale[1]).abs().sqrt()
+    }
+
+    // Derivatives of a surface point with respect to the transformed
+    // coordinates, from those with respect to the original ones, through the
+    // inverse of the rotation and scale.
+    fn tangents(&self, dpdu: Vector3, dpdv: Vector3) -> (Vector3, Vector3) {
+        let (sin, cos) = self.rotation.sin_cos();
+        let (su, sv) = (self.scale[0] as f64, self.scale[1] as f64);
+        let (sin, cos) = (sin as f64, cos as f64);
+        (dpdu * (cos / su) - dpdv * (sin / sv), dpdu * (sin / su) + dpdv * (cos / sv))
+    }
+}
+
+// One level of the mip pyramid, with linear colors.
+struct MipLevel {
+    width: u32,
+    height: u32,
+    texels: Vec<Color>,
+}
+
+impl MipLevel {
+
+    fn texel(&self, x: i64, y: i64, address_mode: AddressMode) -> Color {
+        let x = address_mode.texel_index(x, self.width);
+        let y = address_mode.texel_index(y, self.height);
+        match (x, y, address_mode) {
+            (Some(x), Some(y), _) => self.texels[(y * self.width + x) as usize],
+            (_, _, AddressMode::Border(color)) => color,
+            _ => unreachable!("only bordered textures have texels outside of them"),
+        }
+    }
+
+    fn nearest(&self, texture_coord: &TextureCoords, address_mode: AddressMode) -> Color {
+        let x = (texture_coord.x * self.width as f32).floor() as i64;
+        let y = (texture_coord.y * self.height as f32).floor() as i64;
+        self.texel(x, y, address_mode)
+    }
+
+    // Texel centers sit at half-integer coordinates.
+    fn bilinear(&self, texture_coord: &TextureCoords, address_mode: AddressMode) -> Color {
+        let x = texture_coord.x * self.width as f32 - 0.5;
+        let y = texture_coord.y * self.height as f32 - 0.5;
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let texel = |x, y| self.texel(x, y, address_mode);
+        let top = texel(x0, y0) * (1.0 - fx) + texel(x0 + 1, y0) * fx;
+        let bottom = texel(x0, y0 + 1) * (1.0 - fx) + texel(x0 + 1, y0 + 1) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    // Box filtered to half the size, down to 1x1.
+    fn downsample(&self) -> MipLevel {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (x, y) = (2 * x as i64, 2 * y as i64);
+                // odd sizes reuse the last row or column
+                let texel = |x, y| self.texel(x, y, AddressMode::ClampToEdge);
+                let sum = texel(x, y) + texel(x + 1, y) + texel(x, y + 1) + texel(x + 1, y + 1);
+                texels.push(sum * 0.25);
+            }
+        }
+        MipLevel { width, height, texels }
+    }
+}
+
+// How the 8-bit values of an image map to the values of a texture: images
+// of colors are sRGB encoded, data such as normal maps is stored as is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    Linear,
+}
+
+// Image texture over the unit square of the uv plane, repeated by default.
+// The mip pyramid is built once at load time and shared between clones.
+#[derive(Clone)]
 pub struct Texture {
-    pub image: DynamicImage
+    levels: Arc<Vec<MipLevel>>,
+    pub filter: TextureFilter,
+    pub address_mode: AddressMode,
 }
 
 impl Texture {
 
-    pub fn load_texture(path: &str) -> Result<Texture, String> {
-        let image = image::open(path).map_err(|_| String::from("Texture not found!"))?;
-        Ok(Texture {image})
+    pub fn load_texture(path: &str, color_space: ColorSpace) -> Result<Texture, String> {
+        let image = image::open(path).map_err(|e| format!("could not load texture `{}`: {}", path, e))?;
+        Ok(Texture::from_image(&image, color_space))
+    }
+
+    pub fn from_image(image: &DynamicImage, color_space: ColorSpace) -> Texture {
+        let (width, height) = image.dimensions();
+        let texels = image.pixels()
+            .map(|(_, _, pixel)| match color_space {
+                ColorSpace::Srgb => Color::from_rgba(pixel),
+                ColorSpace::Linear => Color::from_rgba_linear(pixel),
+            })
+            .collect();
+        let mut levels = vec![MipLevel { width, height, texels }];
+        while levels.last().is_some_and(|level| level.width > 1 || level.height > 1) {
+            let next = levels.last().unwrap().downsample();
+            levels.push(next);
+        }
+        Texture { levels: Arc::new(levels), filter: TextureFilter::Trilinear, address_mode: AddressMode::Repeat }
+    }
+
+    pub fn with_filter(mut self, filter: TextureFilter) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_address_mode(mut self, address_mode: AddressMode) -> Self {
+        self.address_mode = address_mode;
+        self
+    }
+
+    pub fn dimension(&self) -> (u32, u32) {
+        (self.levels[0].width, self.levels[0].height)
+    }
+
+    // `footprint` is the width of the lookup in uv units; the mip level is
+    // chosen so that it covers about one texel.
+    pub fn sample(&self, texture_coord: &TextureCoords, footprint: f32) -> Color {
+        match self.filter {
+            TextureFilter::Nearest => self.levels[0].nearest(texture_coord, self.address_mode),
+            TextureFilter::Bilinear => self.levels[0].bilinear(texture_coord, self.address_mode),
+            TextureFilter::Trilinear => {
+                let (width, height) = self.dimension();
+                let texels = footprint * width.max(height) as f32;
+                let lod = if texels > 1.0 { texels.log2().min((self.levels.len() - 1) as f32) } else { 0.0 };
+                let level = lod.floor() as usize;
+                let t = lod - level as f32;
+                let color = self.levels[level].bilinear(texture_coord, self.address_mode);
+                if t > 0.0 {
+                    color * (1.0 - t) + self.levels[level + 1].bilinear(texture_coord, self.address_mode) * t
+                } else {
+                    color
+                }
+            },
+        }
     }
 }
 
 pub enum Coloration {
     Color(Color),
-    Texture(Texture)
+    Texture(Texture),
+    Transformed(Box<Coloration>, UvTransform),
+    Procedural(Box<Procedural>),
 }
 
 impl Coloration  {
 
-    pub fn color(&self, texture_coord: &TextureCoords) -> Color {
+    pub fn is_constant(&self) -> bool {
         match self {
-            Coloration::Color(c) => *c, 
-            Coloration::Texture(texture) => {
-                let texture_image = &texture.image;
-                let width  = texture_image.width();
-                let heigth = texture_image.height();
-                let (x,y) = (wrap(texture_coord.x, width), wrap(texture_coord.y,heigth));
-
-                Color::from_rgba(texture_image.get_pixel(x , y))
-            }
-            
+            Coloration::Color(_) => true,
+            Coloration::Transformed(coloration, _) => coloration.is_constant(),
+            Coloration::Texture(_) | Coloration::Procedural(_) => false,
+        }
+    }
+
+    // Derivatives of a surface point with respect to the coordinates the
+    // coloration is looked up at, from those with respect to the texture
+    // coordinates of the surface.
+    pub fn tangents(&self, dpdu: Vector3, dpdv: Vector3) -> (Vector3, Vector3) {
+        match self {
+            Coloration::Transformed(coloration, transform) => {
+                let (dpdu, dpdv) = transform.tangents(dpdu, dpdv);
+                coloration.tangents(dpdu, dpdv)
+            },
+            _ => (dpdu, dpdv),
         }
     }
-    
-}
 
-fn wrap(val: f32, bound: u32) -> u32 {
-    let coord = (val * (bound as f32)) as i32;
-    let warp_coord = coord.rem_euclid(bound as i32);
-    warp_coord as u32
+    pub fn color(&self, texture_coord: &TextureCoords, position: &Point) -> Color {
+        self.sample(texture_coord, position, 0.0)
+    }
+
+    // Color at a surface point filtered over `footprint` uv units around its
+    // texture coordinates.
+    pub fn sample(&self, texture_coord: &TextureCoords, position: &Point, footprint: f32) -> Color {
+        match self {
+            Coloration::Color(c) => *c,
+            Coloration::Texture(texture) => texture.sample(texture_coord, footprint),
+            Coloration::Transformed(coloration, transform) => coloration.sample(
+                &transform.apply(texture_coord), position, footprint * transform.footprint_scale()),
+            Coloration::Procedural(procedural) => procedural.sample(texture_coord, position, footprint),
+        }
+    }
 }
 
+#[cfg(test)]
+mod tests {
+    use image::RgbImage;
+
+    use super::*;
+
+    #[test]
+    fn srgb_encoding_round_trips() {
+        for i in 0..=1000 {
+            let x = i as f32 / 1000.0;
+            assert!((srgb_encode(srgb_decode(x)) - x).abs() < 1e-5, "{}", x);
+            assert!((srgb_decode(srgb_encode(x)) - x).abs() < 1e-5, "{}", x);
+        }
+        for byte in 0..=255 {
+            let rgba = Rgba([byte, 255 - byte, byte / 2, 255]);
+            assert_eq!(Color::from_rgba(rgba).to_rgba(), rgba);
+        }
+    }
+
+    // 8x8 black and white checkerboard, whose mip levels below the first are
+    // uniformly grey.
+    fn checkerboard(filter: TextureFilter) -> Texture {
+        let image = RgbImage::from_fn(8, 8, |x, y| if (x + y) % 2 == 0 { Rgb([255, 255, 255]) } else { Rgb([0, 0, 0]) });
+        Texture::from_image(&DynamicImage::ImageRgb8(image), ColorSpace::Linear).with_filter(filter)
+    }
+
+    #[test]
+    fn trilinear_lookups_select_the_level_of_the_footprint() {
+        let texture = checkerboard(TextureFilter::Trilinear);
+        // the center of the white texel at the origin
+        let coord = TextureCoords { x: 0.5 / 8.0, y: 0.5 / 8.0 };
+        let value = |footprint: f32| texture.sample(&coord, footprint).red;
+
+        // up to one texel wide, the full resolution level
+        assert!((value(0.0) - 1.0).abs() < 1e-6);
+        assert!((value(1.0 / 8.0) - 1.0).abs() < 1e-6);
+        // two texels wide, level 1
+        assert!((value(2.0 / 8.0) - 0.5).abs() < 1e-6);
+        // in between, a blend of levels 0 and 1 by log2 of the width in texels
+        let t = 1.5f32.log2();
+        assert!((value(1.5 / 8.0) - (1.0 - t + 0.5 * t)).abs() < 1e-5);
+        // wider than the texture, the last level
+        assert!((value(100.0) - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn other_filters_ignore_the_footprint() {
+        let coord = TextureCoords { x: 0.5 / 8.0, y: 0.5 / 8.0 };
+        for filter in [TextureFilter::Nearest, TextureFilter::Bilinear] {
+            assert!((checkerboard(filter).sample(&coord, 1.0).red - 1.0).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn tangents_follow_the_transformed_coordinates() {
+        // a surface whose texture coordinates are its x and y
+        let (dpdu, dpdv) = (Vector3::new(1.0, 0.0, 0.0), Vector3::new(0.0, 1.0, 0.0));
+        let transform = UvTransform { scale: [2.0, -0.5], rotation: 30f32.to_radians(), offset: [0.25, 0.0] };
+        let coloration = Coloration::Transformed(Box::new(Coloration::Color(Color::black())), transform);
+        let (tangent, bitangent) = coloration.tangents(dpdu, dpdv);
+
+        let origin = transform.apply(&TextureCoords { x: 0.3, y: 0.6 });
+        for (step, expected) in [(tangent, (1.0, 0.0)), (bitangent, (0.0, 1.0))] {
+            let (x, y, _) = step.coordinate();
+            let moved = transform.apply(&TextureCoords { x: 0.3 + x as f32 * 1e-2, y: 0.6 + y as f32 * 1e-2 });
+            assert!(((moved.x - origin.x) / 1e-2 - expected.0).abs() < 1e-3);
+            assert!(((moved.y - origin.y) / 1e-2 - expected.1).abs() < 1e-3);
+        }
+    }
+
+    #[test]
+    fn plain_colors_are_constant() {
+        let transform = UvTransform { scale: [2.0, 2.0], rotation: 0.0, offset: [0.0, 0.0] };
+        assert!(Coloration::Transformed(Box::new(Coloration::Color(Color::black())), transform).is_constant());
+        assert!(!Coloration::Texture(checkerboard(TextureFilter::Nearest)).is_constant());
+    }
+}
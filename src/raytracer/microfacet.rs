@@ -0,0 +1,127 @@
+use std::f32::consts::PI;
+
+use rand::Rng;
+
+use super::geometry::Vector3;
+use super::material::Color;
+
+// Cook–Torrance microfacet BRDF: GGX distribution, separable Smith shadowing
+// and Schlick's Fresnel, over a lambertian base. Metals have no diffuse part
+// and tint their reflection with the surface color; dielectrics reflect 4%
+// at normal incidence.
+//
+// Directions point away from the surface: `view` towards the viewer, `light`
+// towards the light. `normal` must face the viewer.
+
+const DIELECTRIC_REFLECTANCE: f32 = 0.04;
+const MIN_ALPHA: f64 = 1e-3;
+
+pub struct Microfacet {
+    pub color: Color,
+    pub albedo: f32,
+    pub roughness: f32,
+    pub metallic: f32,
+}
+
+impl Microfacet {
+
+    fn alpha(&self) -> f64 {
+        ((self.roughness * self.roughness) as f64).max(MIN_ALPHA)
+    }
+
+    fn specular_color(&self) -> Color {
+        let metallic = self.metallic;
+        Color::new(DIELECTRIC_REFLECTANCE, DIELECTRIC_REFLECTANCE, DIELECTRIC_REFLECTANCE) * (1.0 - metallic)
+            + self.color * metallic
+    }
+
+    fn fresnel(&self, cos_theta: f64) -> Color {
+        let f0 = self.specular_color();
+        let weight = (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5) as f32;
+        Color::new(
+            f0.red + (1.0 - f0.red) * weight,
+            f0.green + (1.0 - f0.green) * weight,
+            f0.blue + (1.0 - f0.blue) * weight,
+        )
+    }
+
+    // Reflectance of the lambertian base, before the 1/pi of the BRDF.
+    fn diffuse_reflectance(&self, fresnel: Color) -> Color {
+        let diffuse = self.color * (self.albedo * (1.0 - self.metallic));
+        Color::new(
+            diffuse.red * (1.0 - fresnel.red),
+            diffuse.green * (1.0 - fresnel.green),
+            diffuse.blue * (1.0 - fresnel.blue),
+        )
+    }
+
+    pub fn brdf(&self, normal: &Vector3, view: &Vector3, light: &Vector3) -> Color {
+        let n_dot_v = normal.dot(view);
+        let n_dot_l = normal.dot(light);
+        if n_dot_v <= 0.0 || n_dot_l <= 0.0 {
+            return Color::black();
+        }
+        let half = (*view + *light).normalize();
+        let alpha = self.alpha();
+        let fresnel = self.fresnel(view.dot(&half));
+
+        let specular = ggx(normal.dot(&half), alpha) * smith(n_dot_v, n_dot_l, alpha) / (4.0 * n_dot_v * n_dot_l);
+        fresnel * specular as f32 + self.diffuse_reflectance(fresnel) * (1.0 / PI)
+    }
+
+    // Probability of choosing the specular lobe when sampling a bounce.
+    pub fn specular_probability(&self, normal: &Vector3, view: &Vector3) -> f32 {
+        let fresnel = self.fresnel(normal.dot(view));
+        let specular = fresnel.luminance();
+        let diffuse = self.diffuse_reflectance(fresnel).luminance();
+        if specular + diffuse <= 0.0 {
+            return 1.0;
+        }
+        (specular / (specular + diffuse)).clamp(0.1, 1.0)
+    }
+
+    // Weight of a cosine-sampled bounce off the lambertian base.
+    pub fn diffuse_weight(&self, normal: &Vector3, view: &Vector3) -> Color {
+        self.diffuse_reflectance(self.fresnel(normal.dot(view)))
+    }
+
+    // Direction of a bounce off the specular lobe, sampled from the GGX
+    // distribution of normals, with its weight brdf * cos / pdf. None when the
+    // sampled direction goes below the surface.
+    pub fn sample_specular<R: Rng>(&self, normal: &Vector3, view: &Vector3, rng: &mut R) -> Option<(Vector3, Color)> {
+        let alpha = self.alpha();
+        let u = rng.random::<f64>();
+        let phi = 2.0 * std::f64::consts::PI * rng.random::<f64>();
+        let cos_theta = ((1.0 - u) / (1.0 + (alpha * alpha - 1.0) * u)).sqrt();
+        let sin_theta = (1.0 - cos_theta * cos_theta).max(0.0).sqrt();
+
+        let (tangent, bitangent) = normal.orthonormal_basis();
+        let half = (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + *normal * cos_theta).normalize();
+        let v_dot_h = view.dot(&half);
+        let light = half * (2.0 * v_dot_h) - *view;
+
+        let n_dot_v = normal.dot(view);
+        let n_dot_l = normal.dot(&light);
+        if n_dot_l <= 0.0 || n_dot_v <= 0.0 || v_dot_h <= 0.0 {
+            return None;
+        }
+
+        // D cancels with the pdf D (n.h) / (4 v.h)
+        let weight = smith(n_dot_v, n_dot_l, alpha) * v_dot_h / (n_dot_v * cos_theta);
+        Some((light, self.fresnel(v_dot_h) * weight as f32))
+    }
+}
+
+fn ggx(n_dot_h: f64, alpha: f64) -> f64 {
+    let alpha2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (alpha2 - 1.0) + 1.0;
+    alpha2 / (std::f64::consts::PI * d * d)
+}
+
+fn smith(n_dot_v: f64, n_dot_l: f64, alpha: f64) -> f64 {
+    let g1 = |n_dot_x: f64| {
+        let alpha2 = alpha * alpha;
+        2.0 * n_dot_x / (n_dot_x + (alpha2 + (1.0 - alpha2) * n_dot_x * n_dot_x).sqrt())
+    };
+    g1(n_dot_v) * g1(n_dot_l)
+}
@@ -4,4 +4,17 @@ pub mod scene;
 pub mod material;
 pub mod geometry;
 pub mod camera;
-pub mod light;
\ No newline at end of file
+pub mod light;
+pub mod scene_file;
+pub mod bvh;
+pub mod obj;
+pub mod ies;
+pub mod sampling;
+pub mod integrator;
+pub mod output;
+pub mod tonemap;
+pub mod framebuffer;
+pub mod aov;
+pub mod microfacet;
+pub mod procedural;
+pub mod bump;
\ No newline at end of file
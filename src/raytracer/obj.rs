@@ -0,0 +1,434 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::element::Mesh;
+use super::geometry::{Point, Vector3};
+use super::material::{Color, ColorSpace, Coloration, DEFAULT_ABSORPTION, Material, SurfaceType, Texture, TextureCoords};
+
+// Wavefront OBJ/MTL import.
+//
+// Each group of faces sharing a material becomes one `Mesh`. Polygons are
+// triangulated as fans. Statements the renderer has no use for (groups,
+// smoothing groups, lines, ...) are ignored.
+
+const DEFAULT_ALBEDO: f32 = 0.18;
+const DEFAULT_INDEX: f32 = 1.5;
+
+#[derive(Clone)]
+struct MtlMaterial {
+    diffuse: Color,
+    diffuse_map: Option<PathBuf>,
+    specular: Color,
+    shininess: f32,
+    illumination: u32,
+    dissolve: f32,
+    index: Option<f32>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: Color::new(0.8, 0.8, 0.8),
+            diffuse_map: None,
+            specular: Color::black(),
+            shininess: 0.0,
+            illumination: 2,
+            dissolve: 1.0,
+            index: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+
+    // Kd/map_Kd give the coloration, reflection (illum 3) uses the average of
+    // Ks as reflectivity and transparent materials (d < 1 or illum 4, 6, 7, 9)
+    // become refractive with index Ni. Ks and Ns also give the highlights of
+    // every illumination model from 2 up.
+    fn to_material(&self, textures: &mut HashMap<PathBuf, Texture>) -> Result<Material, String> {
+        let coloration = match &self.diffuse_map {
+            Some(path) => {
+                if !textures.contains_key(path) {
+                    let texture = Texture::load_texture(&path.to_string_lossy(), ColorSpace::Srgb)?;
+                    textures.insert(path.clone(), texture);
+                }
+                Coloration::Texture(textures[path].clone())
+            },
+            None => Coloration::Color(self.diffuse),
+        };
+
+        let transparency = 1.0 - self.dissolve;
+        let surface = if transparency > 0.0 || matches!(self.illumination, 4 | 6 | 7 | 9) {
+            SurfaceType::Refractive {
+                index: self.index.unwrap_or(DEFAULT_INDEX),
+                transparency: if transparency > 0.0 { transparency } else { 1.0 },
+                absorption: DEFAULT_ABSORPTION,
+            }
+        } else if self.illumination == 3 {
+            let specular = self.specular;
+            SurfaceType::Reflective { reflectivity: (specular.red + specular.green + specular.blue) / 3.0 }
+        } else {
+            SurfaceType::Diffuse
+        };
+
+        let specular_color = if self.illumination >= 2 { self.specular } else { Color::black() };
+        Ok(Material {
+            coloration,
+            albedo: DEFAULT_ALBEDO,
+            surface,
+            specular_color,
+            specular_exponent: self.shininess,
+            normal_map: None,
+            bump_map: None,
+            parameter_maps: Vec::new(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct VertexIndex {
+    position: usize,
+    uv: Option<usize>,
+    normal: Option<usize>,
+}
+
+struct Group {
+    material: Option<String>,
+    faces: Vec<[VertexIndex; 3]>,
+}
+
+struct Parser<'a> {
+    path: &'a Path,
+    line: usize,
+}
+
+impl<'a> Parser<'a> {
+
+    fn error(&self, message: String) -> String {
+        format!("{}:{}: {}", self.path.display(), self.line, message)
+    }
+
+    fn float(&self, token: Option<&str>, what: &str) -> Result<f64, String> {
+        let token = token.ok_or_else(|| self.error(format!("missing {}", what)))?;
+        token.parse::<f64>().map_err(|_| self.error(format!("invalid {} `{}`", what, token)))
+    }
+
+    fn floats<const N: usize>(&self, tokens: &mut std::str::SplitWhitespace, statement: &str) -> Result<[f64; N], String> {
+        let mut values = [0.0; N];
+        for (i, value) in values.iter_mut().enumerate() {
+            *value = self.float(tokens.next(), &format!("{} component {}", statement, i + 1))?;
+        }
+        Ok(values)
+    }
+
+    // Resolves a 1-based (or negative, relative) OBJ index.
+    fn index(&self, token: &str, count: usize, what: &str) -> Result<usize, String> {
+        let index = token.parse::<i64>().map_err(|_| self.error(format!("invalid {} index `{}`", what, token)))?;
+        let resolved = if index > 0 { index - 1 } else { count as i64 + index };
+        if index == 0 || resolved < 0 || resolved >= count as i64 {
+            return Err(self.error(format!("{} index {} out of range, {} defined so far", what, index, count)));
+        }
+        Ok(resolved as usize)
+    }
+}
+
+pub fn load_obj(path: &Path) -> Result<Vec<Mesh>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("{}: could not read OBJ file: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut parser = Parser { path, line: 0 };
+    let mut positions: Vec<Point> = Vec::new();
+    let mut uvs: Vec<TextureCoords> = Vec::new();
+    let mut normals: Vec<Vector3> = Vec::new();
+    let mut materials: HashMap<String, MtlMaterial> = HashMap::new();
+    let mut groups: Vec<Group> = vec![Group { material: None, faces: Vec::new() }];
+
+    for (number, line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let statement = match tokens.next() {
+            Some(statement) => statement,
+            None => continue,
+        };
+
+        match statement {
+            "v" => {
+                let [x, y, z] = parser.floats(&mut tokens, "vertex")?;
+                positions.push(Point::new(x, y, z));
+            },
+            "vt" => {
+                let u = parser.float(tokens.next(), "texture coordinate u")?;
+                let v = tokens.next().map(|v| parser.float(Some(v), "texture coordinate v")).transpose()?.unwrap_or(0.0);
+                // OBJ puts v = 0 at the bottom of the image, textures are sampled from the top
+                uvs.push(TextureCoords { x: u as f32, y: (1.0 - v) as f32 });
+            },
+            "vn" => {
+                let [x, y, z] = parser.floats(&mut tokens, "normal")?;
+                normals.push(Vector3::new(x, y, z).normalize());
+            },
+            "f" => {
+                let vertices = tokens
+                    .map(|token| parse_face_vertex(&parser, token, positions.len(), uvs.len(), normals.len()))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if vertices.len() < 3 {
+                    return Err(parser.error(format!("face needs at least 3 vertices, got {}", vertices.len())));
+                }
+                let group = groups.last_mut().unwrap();
+                for i in 1..vertices.len() - 1 {
+                    group.faces.push([vertices[0], vertices[i], vertices[i + 1]]);
+                }
+            },
+            "usemtl" => {
+                let name = tokens.next().ok_or_else(|| parser.error(String::from("missing material name")))?;
+                if !materials.contains_key(name) {
+                    return Err(parser.error(format!("unknown material `{}`", name)));
+                }
+                groups.push(Group { material: Some(name.to_string()), faces: Vec::new() });
+            },
+            "mtllib" => {
+                for file in tokens {
+                    let mtl_path = base_dir.join(file);
+                    materials.extend(load_mtl(&mtl_path).map_err(|e| parser.error(e))?);
+                }
+            },
+            _ => {},
+        }
+    }
+
+    let mut textures = HashMap::new();
+    groups
+        .into_iter()
+        .filter(|group| !group.faces.is_empty())
+        .map(|group| {
+            let material = match &group.material {
+                Some(name) => materials[name].to_material(&mut textures),
+                None => MtlMaterial::default().to_material(&mut textures),
+            }.map_err(|e| format!("{}: {}", path.display(), e))?;
+            Ok(build_mesh(&group.faces, &positions, &uvs, &normals, material))
+        })
+        .collect()
+}
+
+fn parse_face_vertex(parser: &Parser, token: &str, positions: usize, uvs: usize, normals: usize) -> Result<VertexIndex, String> {
+    let mut parts = token.split('/');
+    let position = parser.index(parts.next().unwrap_or(""), positions, "vertex")?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(parser.index(index, uvs, "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(index) => Some(parser.index(index, normals, "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(parser.error(format!("invalid face vertex `{}`", token)));
+    }
+    Ok(VertexIndex { position, uv, normal })
+}
+
+// OBJ indexes positions, uvs and normals separately while meshes share one
+// index, so every distinct combination becomes a mesh vertex. Normals and uvs
+// are only kept when every vertex of the group has one.
+fn build_mesh(faces: &[[VertexIndex; 3]], positions: &[Point], uvs: &[TextureCoords], normals: &[Vector3], material: Material) -> Mesh {
+    let has_uvs = faces.iter().flatten().all(|v| v.uv.is_some());
+    let has_normals = faces.iter().flatten().all(|v| v.normal.is_some());
+
+    let mut mesh = Mesh {
+        positions: Vec::new(),
+        normals: Vec::new(),
+        uvs: Vec::new(),
+        indices: Vec::with_capacity(faces.len()),
+        material,
+    };
+    let mut vertices: HashMap<VertexIndex, usize> = HashMap::new();
+
+    for face in faces {
+        let mut indices = [0; 3];
+        for (slot, vertex) in indices.iter_mut().zip(face) {
+            *slot = *vertices.entry(*vertex).or_insert_with(|| {
+                mesh.positions.push(positions[vertex.position]);
+                if has_uvs {
+                    mesh.uvs.push(uvs[vertex.uv.unwrap()]);
+                }
+                if has_normals {
+                    mesh.normals.push(normals[vertex.normal.unwrap()]);
+                }
+                mesh.positions.len() - 1
+            });
+        }
+        mesh.indices.push(indices);
+    }
+
+    mesh
+}
+
+fn load_mtl(path: &Path) -> Result<HashMap<String, MtlMaterial>, String> {
+    let source = fs::read_to_string(path)
+        .map_err(|e| format!("could not read MTL file {}: {}", path.display(), e))?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+
+    let mut parser = Parser { path, line: 0 };
+    let mut materials = HashMap::new();
+    let mut current: Option<(String, MtlMaterial)> = None;
+
+    for (number, line) in source.lines().enumerate() {
+        parser.line = number + 1;
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        let statement = match tokens.next() {
+            Some(statement) => statement,
+            None => continue,
+        };
+
+        if statement == "newmtl" {
+            let name = tokens.next().ok_or_else(|| parser.error(String::from("missing material name")))?;
+            if let Some((name, material)) = current.take() {
+                materials.insert(name, material);
+            }
+            current = Some((name.to_string(), MtlMaterial::default()));
+            continue;
+        }
+
+        let material = match current.as_mut() {
+            Some((_, material)) => material,
+            None if matches!(statement, "Kd" | "Ks" | "Ka" | "Ke" | "Ns" | "Ni" | "d" | "Tr" | "illum" | "map_Kd") =>
+                return Err(parser.error(format!("`{}` before any `newmtl`", statement))),
+            None => continue,
+        };
+
+        match statement {
+            "Kd" => {
+                let [r, g, b] = parser.floats(&mut tokens, "Kd")?;
+                material.diffuse = Color::new(r as f32, g as f32, b as f32);
+            },
+            "Ks" => {
+                let [r, g, b] = parser.floats(&mut tokens, "Ks")?;
+                material.specular = Color::new(r as f32, g as f32, b as f32);
+            },
+            "Ns" => material.shininess = parser.float(tokens.next(), "Ns")?.max(0.0) as f32,
+            "Ni" => material.index = Some(parser.float(tokens.next(), "Ni")? as f32),
+            "d" => material.dissolve = parser.float(tokens.next(), "d")? as f32,
+            "Tr" => material.dissolve = 1.0 - parser.float(tokens.next(), "Tr")? as f32,
+            "illum" => {
+                let token = tokens.next().ok_or_else(|| parser.error(String::from("missing illum")))?;
+                material.illumination = token.parse()
+                    .map_err(|_| parser.error(format!("invalid illum `{}`", token)))?;
+            },
+            "map_Kd" => {
+                // options such as `-s 1 1 1` come before the file name
+                let file = tokens.last().ok_or_else(|| parser.error(String::from("missing texture file")))?;
+                material.diffuse_map = Some(base_dir.join(file));
+            },
+            _ => {},
+        }
+    }
+
+    if let Some((name, material)) = current {
+        materials.insert(name, material);
+    }
+    Ok(materials)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    // Writes the files in a directory of their own and loads `test.obj`.
+    fn load(test: &str, files: &[(&str, &str)]) -> Result<Vec<Mesh>, String> {
+        let dir = std::env::temp_dir().join(format!("raytracer-obj-{}-{}", test, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, contents) in files {
+            fs::write(dir.join(name), contents).unwrap();
+        }
+        let path: PathBuf = dir.join("test.obj");
+        let meshes = load_obj(&path);
+        fs::remove_dir_all(&dir).unwrap();
+        meshes
+    }
+
+    const QUAD: &str = "\
+mtllib test.mtl
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+# a quad, triangulated as a fan
+f 1/1/1 2/2/1 3/3/1 4/4/1
+usemtl mirror
+f -4 -3 -2
+";
+
+    const MTL: &str = "\
+newmtl mirror
+Kd 1 0 0
+Ks 0.5 0.5 0.5
+illum 3
+";
+
+    #[test]
+    fn loads_faces_per_material() {
+        let meshes = load("quad", &[("test.obj", QUAD), ("test.mtl", MTL)]).unwrap();
+        assert_eq!(meshes.len(), 2);
+
+        let quad = &meshes[0];
+        assert_eq!(quad.positions.len(), 4);
+        assert_eq!(quad.indices, vec![[0, 1, 2], [0, 2, 3]]);
+        assert_eq!(quad.normals.len(), 4);
+        // v is flipped to go down the image
+        assert_eq!((quad.uvs[0].x, quad.uvs[0].y), (0.0, 1.0));
+        assert_eq!((quad.uvs[2].x, quad.uvs[2].y), (1.0, 0.0));
+        assert!(matches!(quad.material.surface, SurfaceType::Diffuse));
+
+        // relative indices, without uvs or normals
+        let triangle = &meshes[1];
+        let positions: Vec<_> = triangle.positions.iter().map(Point::coordinate).collect();
+        assert_eq!(positions, vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (1.0, 1.0, 0.0)]);
+        assert!(triangle.uvs.is_empty() && triangle.normals.is_empty());
+        match triangle.material.surface {
+            SurfaceType::Reflective { reflectivity } => assert_eq!(reflectivity, 0.5),
+            _ => panic!("illum 3 should give a reflective surface"),
+        }
+        match &triangle.material.coloration {
+            Coloration::Color(color) => assert_eq!((color.red, color.green, color.blue), (1.0, 0.0, 0.0)),
+            _ => panic!("Kd should give the color"),
+        }
+    }
+
+    fn load_error(test: &str, obj: &str) -> String {
+        match load(test, &[("test.obj", obj), ("test.mtl", MTL)]) {
+            Ok(_) => panic!("`{}` should not load", obj),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn reports_malformed_statements_with_their_line() {
+        let error = load_error("vertex", "v 0 0 0\nv 1 x 0\n");
+        assert!(error.ends_with("test.obj:2: invalid vertex component 2 `x`"), "{}", error);
+
+        let error = load_error("range", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4\n");
+        assert!(error.ends_with("test.obj:4: vertex index 4 out of range, 3 defined so far"), "{}", error);
+
+        let error = load_error("zero", "v 0 0 0\nv 1 0 0\nv 1 1 0\nf 0 1 2\n");
+        assert!(error.contains("test.obj:4: vertex index 0 out of range"), "{}", error);
+
+        let error = load_error("degenerate", "v 0 0 0\nv 1 0 0\nf 1 2\n");
+        assert!(error.ends_with("test.obj:3: face needs at least 3 vertices, got 2"), "{}", error);
+
+        let error = load_error("material", "mtllib test.mtl\nusemtl glass\n");
+        assert!(error.ends_with("test.obj:2: unknown material `glass`"), "{}", error);
+
+        let error = load_error("mtl", "mtllib missing.mtl\n");
+        assert!(error.contains("test.obj:1: could not read MTL file"), "{}", error);
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use image::codecs::hdr::HdrEncoder;
+use image::{DynamicImage, ImageFormat, Rgb32FImage};
+
+// Floating-point image formats, written with linear values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HdrFormat {
+    OpenExr,
+    Radiance, // RGBE .hdr
+    Pfm,      // Portable Float Map
+}
+
+pub fn save_hdr(image: &Rgb32FImage, path: &Path, format: HdrFormat) -> Result<(), String> {
+    match format {
+        HdrFormat::OpenExr => DynamicImage::ImageRgb32F(image.clone())
+            .save_with_format(path, ImageFormat::OpenExr)
+            .map_err(|e| e.to_string()),
+        HdrFormat::Radiance => {
+            let file = File::create(path).map_err(|e| e.to_string())?;
+            let pixels: Vec<_> = image.pixels().copied().collect();
+            HdrEncoder::new(BufWriter::new(file))
+                .encode(&pixels, image.width() as usize, image.height() as usize)
+                .map_err(|e| e.to_string())
+        },
+        HdrFormat::Pfm => File::create(path)
+            .and_then(|file| write_pfm(image, BufWriter::new(file)))
+            .map_err(|e| e.to_string()),
+    }
+}
+
+// Color PFM: a text header, then little-endian floats with the rows stored
+// from the bottom of the image up. The negative scale marks little-endian.
+fn write_pfm<W: Write>(image: &Rgb32FImage, mut writer: W) -> std::io::Result<()> {
+    write!(writer, "PF\n{} {}\n-1.0\n", image.width(), image.height())?;
+    for row in image.rows().rev() {
+        for pixel in row {
+            for channel in pixel.0 {
+                writer.write_all(&channel.to_le_bytes())?;
+            }
+        }
+    }
+    writer.flush()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    #[test]
+    fn pfm_rows_go_from_the_bottom_up() {
+        let mut image = Rgb32FImage::new(2, 2);
+        image.put_pixel(0, 0, Rgb([1.0, 2.0, 3.0]));
+        image.put_pixel(1, 1, Rgb([-4.5, 0.25, 1e6]));
+        let mut bytes = Vec::new();
+        write_pfm(&image, &mut bytes).unwrap();
+
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let values: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, [
+            0.0, 0.0, 0.0, -4.5, 0.25, 1e6, // bottom row
+            1.0, 2.0, 3.0, 0.0, 0.0, 0.0,   // top row
+        ]);
+    }
+}
@@ -0,0 +1,222 @@
+use std::f64::consts::PI;
+
+use super::geometry::Point;
+use super::material::{Color, Coloration, TextureCoords};
+
+// Colorations computed from the surface point instead of looked up in an
+// image. A pattern gives a value in [0, 1] blending two colorations, which
+// may be patterns or textures themselves.
+
+#[derive(Debug, Clone, Copy)]
+pub enum Pattern {
+    Checker,
+    Stripes,  // along the first axis
+    Gradient, // from 0 to 1 along the first axis
+    Noise,
+    Turbulence { octaves: u32 },
+    Marble { octaves: u32, distortion: f64 },
+    Wood { distortion: f64 }, // rings around the uv origin or the y axis
+}
+
+// What a pattern is evaluated from: the texture coordinates, as a point of
+// the z = 0 plane, or the position of the surface point in the scene.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Domain {
+    Uv,
+    Position,
+}
+
+pub struct Procedural {
+    pub pattern: Pattern,
+    pub domain: Domain,
+    pub scale: f64, // size of the features of the pattern
+    pub colors: [Coloration; 2],
+}
+
+impl Procedural {
+
+    // Checkers and stripes over texture coordinates are box filtered over
+    // `footprint`; patterns over positions are not filtered.
+    pub fn sample(&self, texture_coord: &TextureCoords, position: &Point, footprint: f32) -> Color {
+        let (point, width) = match self.domain {
+            Domain::Uv => {
+                let point = Point::new(texture_coord.x as f64, texture_coord.y as f64, 0.0);
+                (point, footprint as f64)
+            },
+            Domain::Position => (*position, 0.0),
+        };
+        let (x, y, z) = point.coordinate();
+        let point = Point::new(x / self.scale, y / self.scale, z / self.scale);
+
+        let t = self.value(&point, width / self.scale) as f32;
+        let [first, second] = &self.colors;
+        if t <= 0.0 {
+            first.sample(texture_coord, position, footprint)
+        } else if t >= 1.0 {
+            second.sample(texture_coord, position, footprint)
+        } else {
+            first.sample(texture_coord, position, footprint) * (1.0 - t)
+                + second.sample(texture_coord, position, footprint) * t
+        }
+    }
+
+    fn value(&self, point: &Point, width: f64) -> f64 {
+        let (x, y, z) = point.coordinate();
+        match self.pattern {
+            Pattern::Checker => {
+                let mut sign = square_wave(x, width) * square_wave(y, width);
+                if self.domain == Domain::Position {
+                    sign *= square_wave(z, width);
+                }
+                0.5 - 0.5 * sign
+            },
+            Pattern::Stripes => 0.5 - 0.5 * square_wave(x, width),
+            Pattern::Gradient => x.clamp(0.0, 1.0),
+            Pattern::Noise => 0.5 + 0.5 * noise(point),
+            Pattern::Turbulence { octaves } => turbulence(point, octaves).min(1.0),
+            Pattern::Marble { octaves, distortion } =>
+                0.5 + 0.5 * ((x + distortion * turbulence(point, octaves)) * PI).sin(),
+            Pattern::Wood { distortion } => {
+                let radius = match self.domain {
+                    Domain::Uv => (x * x + y * y).sqrt(),
+                    Domain::Position => (x * x + z * z).sqrt(),
+                };
+                let rings = radius + distortion * noise(point);
+                rings - rings.floor()
+            },
+        }
+    }
+}
+
+// Square wave, 1 over even unit intervals and -1 over odd ones, averaged
+// over `width` around x.
+fn square_wave(x: f64, width: f64) -> f64 {
+    if width <= 0.0 {
+        return if x.floor().rem_euclid(2.0) == 0.0 { 1.0 } else { -1.0 };
+    }
+    // the integral of the square wave is a triangle wave
+    let integral = |x: f64| 1.0 - (x.rem_euclid(2.0) - 1.0).abs();
+    ((integral(x + width / 2.0) - integral(x - width / 2.0)) / width).clamp(-1.0, 1.0)
+}
+
+// Sum of octaves of the absolute value of noise, each twice the frequency
+// and half the amplitude of the previous one.
+pub fn turbulence(point: &Point, octaves: u32) -> f64 {
+    let (x, y, z) = point.coordinate();
+    let mut sum = 0.0;
+    let mut frequency = 1.0;
+    for _ in 0..octaves {
+        sum += noise(&Point::new(x * frequency, y * frequency, z * frequency)).abs() / frequency;
+        frequency *= 2.0;
+    }
+    sum
+}
+
+// Perlin's improved gradient noise, in [-1, 1] and 0 at integer points.
+// Gradients are chosen by hashing the lattice points instead of through a
+// permutation table, so the noise does not repeat.
+pub fn noise(point: &Point) -> f64 {
+    let (x, y, z) = point.coordinate();
+    let (xi, yi, zi) = (x.floor() as i64, y.floor() as i64, z.floor() as i64);
+    let (x, y, z) = (x - x.floor(), y - y.floor(), z - z.floor());
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        let hash = hash(xi + dx, yi + dy, zi + dz);
+        gradient(hash, x - dx as f64, y - dy as f64, z - dz as f64)
+    };
+    let lerp = |t: f64, a: f64, b: f64| a + t * (b - a);
+
+    lerp(w,
+        lerp(v, lerp(u, corner(0, 0, 0), corner(1, 0, 0)), lerp(u, corner(0, 1, 0), corner(1, 1, 0))),
+        lerp(v, lerp(u, corner(0, 0, 1), corner(1, 0, 1)), lerp(u, corner(0, 1, 1), corner(1, 1, 1))))
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn hash(x: i64, y: i64, z: i64) -> u32 {
+    let mut hash = (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0x5bd1_e995);
+    hash ^ (hash >> 15)
+}
+
+// Dot product with one of the twelve directions to the edges of a cube.
+fn gradient(hash: u32, x: f64, y: f64, z: f64) -> f64 {
+    match hash % 12 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x + z,
+        5 => -x + z,
+        6 => x - z,
+        7 => -x - z,
+        8 => y + z,
+        9 => -y + z,
+        10 => y - z,
+        _ => -y - z,
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{Rng, SeedableRng, rngs::SmallRng};
+
+    use super::*;
+
+    fn procedural(pattern: Pattern, domain: Domain) -> Procedural {
+        let colors = [Coloration::Color(Color::black()), Coloration::Color(Color::new(1.0, 1.0, 1.0))];
+        Procedural { pattern, domain, scale: 1.0, colors }
+    }
+
+    fn random_point(rng: &mut SmallRng) -> Point {
+        Point::new(rng.random_range(-10.0..10.0), rng.random_range(-10.0..10.0), rng.random_range(-10.0..10.0))
+    }
+
+    #[test]
+    fn box_filtered_checkers_blend_to_grey() {
+        let checker = procedural(Pattern::Checker, Domain::Uv);
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let point = Point::new(rng.random_range(-10.0..10.0), rng.random_range(-10.0..10.0), 0.0);
+            let (x, y, _) = point.coordinate();
+            let plain = if (x.floor() + y.floor()).rem_euclid(2.0) == 0.0 { 0.0 } else { 1.0 };
+            assert_eq!(checker.value(&point, 0.0), plain);
+            assert!((checker.value(&point, 1000.0) - 0.5).abs() < 1e-2);
+        }
+    }
+
+    #[test]
+    fn stripes_and_gradients_stay_in_range() {
+        let stripes = procedural(Pattern::Stripes, Domain::Position);
+        let gradient = procedural(Pattern::Gradient, Domain::Position);
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let point = random_point(&mut rng);
+            for width in [0.0, 0.3, 2.5] {
+                let value = stripes.value(&point, width);
+                assert!((0.0..=1.0).contains(&value), "{value}");
+            }
+            assert!((0.0..=1.0).contains(&gradient.value(&point, 0.0)));
+        }
+        assert_eq!(gradient.value(&Point::new(0.25, 3.0, -2.0), 0.0), 0.25);
+    }
+
+    #[test]
+    fn noise_vanishes_on_the_lattice_and_stays_in_range() {
+        let mut rng = SmallRng::seed_from_u64(0);
+        for _ in 0..10000 {
+            let point = random_point(&mut rng);
+            let (x, y, z) = point.coordinate();
+            assert_eq!(noise(&Point::new(x.floor(), y.floor(), z.floor())), 0.0);
+            let value = noise(&point);
+            assert!((-1.0..=1.0).contains(&value), "{value}");
+        }
+    }
+}
@@ -1,47 +1,90 @@
-use super::element::Element;
-use super::geometry::Point;
+use super::element::PlacedElement;
+use super::geometry::{BoundingBox, Point};
 use super::geometry::Vector3;
 use super::material::TextureCoords;
 
+// Cone around a ray covering the footprint of a pixel, used to filter
+// textures. `width` is the width of the cone at the ray origin and `spread`
+// its angle in radians; the default cone is a plain ray.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RayCone {
+    pub width: f64,
+    pub spread: f64,
+}
+
+impl RayCone {
+    pub fn new(width: f64, spread: f64) -> Self {
+        Self { width, spread }
+    }
+
+    // The cone at `distance` along the ray.
+    pub fn at(&self, distance: f64) -> RayCone {
+        RayCone::new(self.width + self.spread * distance, self.spread)
+    }
+}
+
 pub struct Ray {
     pub origin: Point,
-    pub direction: Vector3
+    pub direction: Vector3,
+    pub cone: RayCone,
+    // whether the ray travels inside a refractive material it was refracted
+    // into, and is absorbed by it
+    pub in_medium: bool,
 }
 
 impl Ray {
     pub fn new(origin: Point, direction: Vector3) -> Self {
-        Self { origin, direction }
+        Self { origin, direction, cone: RayCone::default(), in_medium: false }
+    }
+
+    pub fn with_cone(mut self, cone: RayCone) -> Self {
+        self.cone = cone;
+        self
     }
 
-    pub fn create_reflection(normal: Vector3, incident: Vector3, intersection: Point, bias: f64) -> Ray {
+    pub fn with_medium(mut self, in_medium: bool) -> Self {
+        self.in_medium = in_medium;
+        self
+    }
+
+    // Secondary rays leave from a point moved by `bias` along the geometric
+    // normal, given on the side the incident ray arrived from: the shading
+    // normal can tilt below the surface and start them on the wrong side.
+    pub fn create_reflection(normal: Vector3, geometric_normal: Vector3, incident: Vector3, intersection: Point, bias: f64) -> Ray {
         Ray {
-            origin: intersection + (normal * bias),
+            origin: intersection + (geometric_normal * bias),
             direction: incident - (2.0 * incident.dot(&normal) * normal),
+            cone: RayCone::default(),
+            in_medium: false,
         }
     }
 
-    pub fn create_shadow(hit_point: &Point, surface_normal: Vector3, light_direction: Vector3, bias: f64) -> Ray {
+    pub fn create_shadow(hit_point: &Point, geometric_normal: Vector3, light_direction: Vector3, bias: f64) -> Ray {
         Ray {
-            origin: *hit_point + surface_normal * bias,
+            origin: *hit_point + geometric_normal * bias,
             direction: light_direction,
+            cone: RayCone::default(),
+            in_medium: false,
         }
     } 
 
-    pub fn create_transmission(surface_normal: Vector3, incident: Vector3, intersection: Point, bias: f64, index: f32) -> Option<Ray> {
+    // Here both normals are outward and the geometric one tells whether the
+    // ray leaves the material.
+    pub fn create_transmission(surface_normal: Vector3, geometric_normal: Vector3, incident: Vector3, intersection: Point, bias: f64, index: f32) -> Option<Ray> {
         let  mut normal = surface_normal;
+        let mut offset = geometric_normal;
         let mut eta_t = index as f64;
         let mut eta_i = 1.0f64;
 
-        let mut i_dot_n = incident.dot(&normal);
-        if i_dot_n < 0.0 {
-            //Outside the surface
-            i_dot_n = -i_dot_n;
-        } else {
-            //Inside the surface; invert the normal and swap the indices of refraction
+        let inside = incident.dot(&geometric_normal) > 0.0;
+        if inside {
+            //Inside the surface; invert the normals and swap the indices of refraction
             normal = -normal;
+            offset = -offset;
             eta_t = 1.0;
             eta_i = index as f64;
         }
+        let i_dot_n = (-incident.dot(&normal)).max(0.0);
 
         let eta = eta_i / eta_t;
         let k = 1.0 - (eta * eta) * (1.0 - i_dot_n * i_dot_n);
@@ -49,8 +92,10 @@ impl Ray {
             None
         } else {
             Some(Ray {
-                origin: intersection + (normal * -bias),
+                origin: intersection + (offset * -bias),
                 direction: (incident + i_dot_n * normal) * eta - normal * k.sqrt(),
+                cone: RayCone::default(),
+                in_medium: !inside,
             })
         }
     }
@@ -60,16 +105,19 @@ pub trait Intersectable {
     fn intersect(&self, ray: &Ray) -> Option<f64>;
     fn surface_normal(&self, hit_point: &Point) -> Vector3;
     fn texture_coords(&self, hit_point: &Point) -> TextureCoords;
+    // None for unbounded shapes such as planes
+    fn bounding_box(&self) -> Option<BoundingBox>;
 }
 
 pub struct Intersection<'a> {
     pub distance: f64,
-    pub element: &'a Element,
+    pub element: PlacedElement<'a>,
+    pub index: usize, // of the element or instance in the scene
 }
 
 
 impl<'a> Intersection<'a> {
-    pub fn new(distance: f64, element: &'a Element) -> Intersection<'a> {
-        Self{distance, element}
+    pub fn new(distance: f64, element: PlacedElement<'a>, index: usize) -> Intersection<'a> {
+        Self{distance, element, index}
     }
 }
\ No newline at end of file
@@ -0,0 +1,211 @@
+use rand::Rng;
+
+// Where the samples of a pixel are placed, as points of the unit square.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SamplePattern {
+    Regular,  // grid of cell centers, count rounded down to a square
+    Jittered, // one random point per grid cell, count rounded down to a square
+    Halton,   // bases 2 and 3, randomly shifted per pixel
+    Sobol,    // first two Sobol dimensions, randomly scrambled per pixel
+}
+
+impl SamplePattern {
+
+    pub fn samples<R: Rng>(&self, count: u32, rng: &mut R) -> Vec<(f64, f64)> {
+        let count = count.max(1);
+        match self {
+            SamplePattern::Regular => grid(count, || (0.5, 0.5)),
+            SamplePattern::Jittered => grid(count, || (rng.random(), rng.random())),
+            SamplePattern::Halton => {
+                let (shift_x, shift_y) = (rng.random::<f64>(), rng.random::<f64>());
+                (0..count)
+                    .map(|i| ((radical_inverse(i, 2) + shift_x).fract(), (radical_inverse(i, 3) + shift_y).fract()))
+                    .collect()
+            },
+            SamplePattern::Sobol => {
+                let (scramble_x, scramble_y) = (rng.random::<u32>(), rng.random::<u32>());
+                (0..count)
+                    .map(|i| (to_unit(i.reverse_bits() ^ scramble_x), to_unit(sobol_second_dimension(i) ^ scramble_y)))
+                    .collect()
+            },
+        }
+    }
+}
+
+fn grid_size(count: u32) -> u32 {
+    ((count as f64).sqrt() as u32).max(1)
+}
+
+// One sample per cell of a square grid, at the given offset within the cell.
+fn grid<F: FnMut() -> (f64, f64)>(count: u32, mut offset: F) -> Vec<(f64, f64)> {
+    let size = grid_size(count);
+    let mut samples = Vec::with_capacity((size * size) as usize);
+    for sx in 0..size {
+        for sy in 0..size {
+            let (ox, oy) = offset();
+            samples.push((((sx as f64) + ox) / (size as f64), ((sy as f64) + oy) / (size as f64)));
+        }
+    }
+    samples
+}
+
+fn radical_inverse(mut i: u32, base: u32) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut factor = inverse_base;
+    let mut result = 0.0;
+    while i > 0 {
+        result += (i % base) as f64 * factor;
+        i /= base;
+        factor *= inverse_base;
+    }
+    result
+}
+
+// Generator matrix of the second Sobol dimension (primitive polynomial x + 1).
+fn sobol_second_dimension(mut i: u32) -> u32 {
+    let mut result = 0;
+    let mut direction = 1u32 << 31;
+    while i > 0 {
+        if i & 1 == 1 {
+            result ^= direction;
+        }
+        i >>= 1;
+        direction ^= direction >> 1;
+    }
+    result
+}
+
+fn to_unit(bits: u32) -> f64 {
+    bits as f64 / (1u64 << 32) as f64
+}
+
+// Shirley–Chiu concentric mapping from the unit square to the unit disk,
+// which keeps stratified samples well distributed.
+pub fn concentric_disk((x, y): (f64, f64)) -> (f64, f64) {
+    let (a, b) = (2.0 * x - 1.0, 2.0 * y - 1.0);
+    if a == 0.0 && b == 0.0 {
+        return (0.0, 0.0);
+    }
+    let (radius, theta) = if a.abs() > b.abs() {
+        (a, std::f64::consts::FRAC_PI_4 * (b / a))
+    } else {
+        (b, std::f64::consts::FRAC_PI_2 - std::f64::consts::FRAC_PI_4 * (a / b))
+    };
+    (radius * theta.cos(), radius * theta.sin())
+}
+
+// Reconstruction filter applied to the samples of a pixel, as a function of
+// their offset from the pixel center in pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+}
+
+impl Filter {
+
+    pub fn radius(&self) -> f64 {
+        match self {
+            Filter::Box => 0.5,
+            Filter::Tent => 1.0,
+            Filter::Gaussian => 1.5,
+            Filter::Mitchell => 2.0,
+        }
+    }
+
+    pub fn evaluate(&self, x: f64, y: f64) -> f64 {
+        self.evaluate_1d(x) * self.evaluate_1d(y)
+    }
+
+    fn evaluate_1d(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        let x = x.abs();
+        if x > radius {
+            return 0.0;
+        }
+        match self {
+            Filter::Box => 1.0,
+            Filter::Tent => radius - x,
+            Filter::Gaussian => {
+                const ALPHA: f64 = 2.0;
+                ((-ALPHA * x * x).exp() - (-ALPHA * radius * radius).exp()).max(0.0)
+            },
+            Filter::Mitchell => mitchell(x / radius * 2.0),
+        }
+    }
+}
+
+// Mitchell–Netravali with B = C = 1/3, x in [0, 2].
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+    if x < 1.0 {
+        ((12.0 - 9.0 * B - 6.0 * C) * x * x * x + (-18.0 + 12.0 * B + 6.0 * C) * x * x + (6.0 - 2.0 * B)) / 6.0
+    } else {
+        ((-B - 6.0 * C) * x * x * x + (6.0 * B + 30.0 * C) * x * x + (-12.0 * B - 48.0 * C) * x + (8.0 * B + 24.0 * C)) / 6.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{SeedableRng, rngs::SmallRng};
+
+    use super::*;
+
+    // Number of samples in every cell of a grid of `columns` by `rows` cells.
+    fn cell_counts(samples: &[(f64, f64)], columns: usize, rows: usize) -> Vec<usize> {
+        let mut counts = vec![0; columns * rows];
+        for &(x, y) in samples {
+            assert!((0.0..1.0).contains(&x) && (0.0..1.0).contains(&y), "sample ({}, {}) outside the unit square", x, y);
+            counts[(y * rows as f64) as usize * columns + (x * columns as f64) as usize] += 1;
+        }
+        counts
+    }
+
+    #[test]
+    fn sobol_samples_stratify_every_elementary_interval() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        let samples = SamplePattern::Sobol.samples(16, &mut rng);
+        for (columns, rows) in [(1, 16), (2, 8), (4, 4), (8, 2), (16, 1)] {
+            assert!(cell_counts(&samples, columns, rows).iter().all(|&count| count == 1), "{}x{} cells", columns, rows);
+        }
+    }
+
+    #[test]
+    fn halton_samples_follow_the_radical_inverses() {
+        assert_eq!(radical_inverse(0, 2), 0.0);
+        assert_eq!(radical_inverse(1, 2), 0.5);
+        assert_eq!(radical_inverse(6, 2), 0.375);
+        assert!((radical_inverse(5, 3) - 7.0 / 9.0).abs() < 1e-12);
+
+        let unshifted: Vec<_> = (0..6).map(|i| (radical_inverse(i, 2), radical_inverse(i, 3))).collect();
+        assert!(cell_counts(&unshifted, 2, 3).iter().all(|&count| count == 1));
+
+        // the shift moves every point the same way around the torus, which
+        // keeps them stratified over cells wrapped around it; the first point
+        // is the shift itself
+        let circle_distance = |a: f64, b: f64| {
+            let d = (a - b).rem_euclid(1.0);
+            d.min(1.0 - d)
+        };
+        for seed in 0..100 {
+            let mut rng = SmallRng::seed_from_u64(seed);
+            let samples = SamplePattern::Halton.samples(6, &mut rng);
+            let (shift_x, shift_y) = samples[0];
+            for (&(x, y), &(ux, uy)) in samples.iter().zip(&unshifted) {
+                assert!(circle_distance(x - shift_x, ux) < 1e-9 && circle_distance(y - shift_y, uy) < 1e-9, "seed {}", seed);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_patterns_round_down_to_a_square() {
+        let mut rng = SmallRng::seed_from_u64(7);
+        assert_eq!(SamplePattern::Regular.samples(10, &mut rng), vec![(1.0 / 6.0, 1.0 / 6.0), (1.0 / 6.0, 0.5), (1.0 / 6.0, 5.0 / 6.0),
+            (0.5, 1.0 / 6.0), (0.5, 0.5), (0.5, 5.0 / 6.0), (5.0 / 6.0, 1.0 / 6.0), (5.0 / 6.0, 0.5), (5.0 / 6.0, 5.0 / 6.0)]);
+        let jittered = SamplePattern::Jittered.samples(16, &mut rng);
+        assert!(cell_counts(&jittered, 4, 4).iter().all(|&count| count == 1));
+    }
+}
@@ -1,17 +1,43 @@
 
 
-use image::{DynamicImage, GenericImage};
-use super::{element::Element, camera::Camera, material::{Color, SurfaceType}, ray::{Intersectable, Intersection, Ray}, light::Light, geometry::{Vector3, Point}};
-
-const MAX_RECURSION_DEPTH : i32 = 10;
+use std::path::Path;
+
+use rand::{Rng, SeedableRng, rngs::SmallRng};
+use rayon::{ThreadPoolBuilder, prelude::*};
+use super::aov::{self, Aov};
+use super::bump::{shading_normal, uv_rate};
+use super::bvh::Bvh;
+use super::framebuffer::Framebuffer;
+use super::integrator::{self, Integrator};
+use super::microfacet::Microfacet;
+use super::sampling::{Filter, SamplePattern};
+use super::scene_file;
+use super::tonemap::ToneMapping;
+use super::{element::{Element, Instance, PlacedElement}, camera::Camera, material::{Color, MaterialSample, SurfaceType, transmittance}, ray::{Intersectable, Intersection, Ray, RayCone}, light::Light, geometry::{Vector3, Point}};
+
+const MAX_RECURSION_DEPTH : u32 = 10;
+const TILE_SIZE: u32 = 32;
 
 pub struct Scene {
     pub height: u32, 
     pub width: u32, 
-    pub elements:  Vec<Element>,
+    elements:  Vec<Element>,
+    instances: Vec<Instance>,
+    object_ids: Vec<u32>, // of the elements, then of the instances
+    bvh: Bvh,
     pub camera: Camera,
     pub lights: Vec<Light>,
     pub shadow_bias: f64,
+    pub max_recursion_depth: u32,
+    pub integrator: Integrator,
+    pub samples: u32, // samples per pixel
+    pub sample_pattern: SamplePattern,
+    pub filter: Filter,
+    pub threads: usize, // render threads, 0 uses every available core
+    pub tone_mapping: ToneMapping,
+    pub exposure: f32, // stops
+    pub white_point: f32, // for extended Reinhard
+    pub aovs: Vec<Aov>,
 }
 
 
@@ -19,51 +45,282 @@ impl Scene {
     pub fn new(height: u32, width: u32, elements: Vec<Element>, lights: Vec<Light>) -> Self {
         let aspect_ratio = (width as f64) / (height as f64);
         let camera = Camera::default_with_aspect_ratio(aspect_ratio);
-        Self {height, width, elements , camera, lights,  shadow_bias: 1e-13}
+        let bvh = Bvh::build(&elements);
+        let object_ids = (1..=elements.len() as u32).collect();
+        Self {
+            height,
+            width,
+            elements,
+            instances: Vec::new(),
+            object_ids,
+            bvh,
+            camera,
+            lights,
+            shadow_bias: 1e-13,
+            max_recursion_depth: MAX_RECURSION_DEPTH,
+            integrator: Integrator::Whitted,
+            samples: 1,
+            sample_pattern: SamplePattern::Regular,
+            filter: Filter::Box,
+            threads: 0,
+            tone_mapping: ToneMapping::Clamp,
+            exposure: 0.0,
+            white_point: 4.0,
+            aovs: Vec::new(),
+        }
+    }
+
+    // Uses the given camera instead of the default one; its aspect ratio is
+    // adjusted to the scene dimension.
+    pub fn with_camera(mut self, mut camera: Camera) -> Self {
+        camera.set_aspect_ratio((self.width as f64) / (self.height as f64));
+        self.camera = camera;
+        self
+    }
+
+    // Places instances of objects in the scene, next to its elements. Each
+    // instance gets its own object id, following those of the elements.
+    pub fn with_instances(mut self, instances: Vec<Instance>) -> Self {
+        let first_id = self.elements.len() as u32 + 1;
+        self.object_ids.truncate(self.elements.len());
+        self.object_ids.extend(first_id..first_id + instances.len() as u32);
+        self.instances = instances;
+        self.bvh = Bvh::from_bounds(self.elements.iter().map(Intersectable::bounding_box)
+            .chain(self.instances.iter().map(Instance::bounding_box)));
+        self
+    }
+
+    // Assigns the id reported by the object id AOV to every element, then to
+    // every instance; by default each has its own id. 0 is left for the
+    // background.
+    pub fn with_object_ids(mut self, object_ids: Vec<u32>) -> Result<Self, String> {
+        let expected = self.elements.len() + self.instances.len();
+        if object_ids.len() != expected {
+            return Err(format!("{} object ids given for {} elements and instances", object_ids.len(), expected));
+        }
+        self.object_ids = object_ids;
+        Ok(self)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, String> {
+        scene_file::load(path.as_ref())
     }
 
     pub fn dimension(&self)  -> (u32, u32){
         (self.width, self.height)
     }
 
-    pub fn trace(&self, ray: &Ray) -> Option<Intersection> {
-        self.elements
-            .iter()
-            .filter_map(|elt| elt.intersect(ray).map(|d| Intersection::new(d, elt)))
-            .min_by(|i1, i2| i1.distance.partial_cmp(&i2.distance).unwrap())
+    pub fn set_dimension(&mut self, width: u32, height: u32) {
+        let aspect_ratio = (width as f64) / (height as f64);
+        self.width = width;
+        self.height = height;
+        self.camera.set_aspect_ratio(aspect_ratio);
+    }
 
+    pub fn elements(&self) -> &[Element] {
+        &self.elements
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn object_id(&self, index: usize) -> u32 {
+        self.object_ids[index]
+    }
+
+    // The index of the intersection is that of the element, or the number of
+    // elements plus that of the instance.
+    pub fn trace(&self, ray: &Ray) -> Option<Intersection<'_>> {
+        let element_count = self.elements.len();
+        self.bvh.closest(ray, |index| match self.instances.get(index.wrapping_sub(element_count)) {
+            Some(instance) => instance.intersect(ray).map(|intersection| Intersection { index, ..intersection }),
+            None => {
+                let element = &self.elements[index];
+                element.intersect(ray).map(|distance| Intersection::new(distance, PlacedElement::new(element), index))
+            },
+        })
     }
 }
 
-fn shade_diffuse(scene: &Scene, element: &Element, hit_point: Point, surface_normal: Vector3)  -> Color {
-    let mut color  = Color::black();
+// Cosine below which the footprint of a ray cone on a surface stops growing.
+const MIN_FOOTPRINT_COSINE: f64 = 0.05;
+
+// What shading a hit needs to know of the surface. `normal` faces the side
+// lights are reflected on and `material` is the material at the hit.
+pub(crate) struct ShadingPoint<'a> {
+    pub element: PlacedElement<'a>,
+    pub hit_point: Point,
+    // shading and geometric normals, both on the side the ray arrived from
+    pub normal: Vector3,
+    pub geometric_normal: Vector3,
+    pub view: Vector3,
+    pub material: MaterialSample,
+    // whether the ray arrived from behind the surface
+    pub inside: bool,
+}
+
+impl ShadingPoint<'_> {
+
+    // Shading and geometric normals on the front side of the surface, as
+    // refraction expects them.
+    pub fn outward_normals(&self) -> (Vector3, Vector3) {
+        if self.inside {
+            (-self.normal, -self.geometric_normal)
+        } else {
+            (self.normal, self.geometric_normal)
+        }
+    }
+}
+
+// Triangles are hit from both faces and refractive surfaces from inside; the
+// side the ray arrived from is told by the face normal and shaded.
+pub(crate) fn shading_point<'a>(ray: &Ray, intersection: &Intersection<'a>) -> ShadingPoint<'a> {
+    let element = intersection.element;
+    let hit_point = ray.origin + ray.direction * intersection.distance;
+    let geometric_normal = element.face_normal(&hit_point);
+    let footprint = texture_footprint(&element, ray, intersection.distance, hit_point, geometric_normal);
+    let surface_normal = shading_normal(&element, &hit_point, element.surface_normal(&hit_point), footprint);
+    let inside = ray.direction.dot(&geometric_normal) > 0.0;
+    ShadingPoint {
+        element,
+        hit_point,
+        normal: if inside { -surface_normal } else { surface_normal },
+        geometric_normal: if inside { -geometric_normal } else { geometric_normal },
+        view: -ray.direction,
+        material: sample_material(&element, hit_point, footprint),
+        inside,
+    }
+}
+
+// Material of a surface point, with its maps filtered over `footprint`.
+fn sample_material(element: &PlacedElement, hit_point: Point, footprint: f32) -> MaterialSample {
     let texture_coord = element.texture_coords(&hit_point);
+    element.material().sample(&texture_coord, &hit_point, footprint)
+}
+
+// Width in uv units of the area covered by the cone of a ray hitting a
+// surface at `distance`, from finite differences of the texture coordinates along the two
+// axes of the ellipse the cone cuts out of it. The geometric mean of the axes
+// keeps grazing surfaces from blurring as much as the longest axis would.
+fn texture_footprint(element: &PlacedElement, ray: &Ray, distance: f64, hit_point: Point, normal: Vector3) -> f32 {
+    let direction = &ray.direction;
+    let cone_width = ray.cone.at(distance).width;
+    // plain materials do not need the extra texture lookups
+    if cone_width <= 0.0 || !element.material().has_maps() {
+        return 0.0;
+    }
+    let cos = normal.dot(direction).abs().max(MIN_FOOTPRINT_COSINE);
+
+    // the footprint stretches along the direction of the ray projected onto
+    // the surface and keeps the cone width across it
+    let along = *direction - normal * normal.dot(direction);
+    let (major, minor) = if along.length() > 1e-9 {
+        let major = along.normalize();
+        (major, normal.cross(&major))
+    } else {
+        normal.orthonormal_basis()
+    };
+
+    let texture_coord = element.texture_coords(&hit_point);
+    let rate = |axis: Vector3| {
+        let (du, dv) = uv_rate(element, &texture_coord, &hit_point, axis);
+        (du * du + dv * dv).sqrt()
+    };
+    ((rate(major) * cone_width / cos) * (rate(minor) * cone_width)).sqrt() as f32
+}
+
+fn shade_diffuse<R: Rng>(scene: &Scene, point: &ShadingPoint, rng: &mut R)  -> Color {
+    direct_lighting(scene, point, rng)
+}
 
+// Reflection of every light reaching the hit point towards `view`, the
+// direction to the viewer.
+pub(crate) fn direct_lighting<R: Rng>(scene: &Scene, point: &ShadingPoint, rng: &mut R)  -> Color {
+    let mut color  = Color::black();
     for light in &scene.lights {
-        let direction_to_light = light.direction_from(&hit_point);
-        let shadow_ray = Ray::create_shadow(&hit_point, surface_normal, direction_to_light, scene.shadow_bias); 
+        color = color + light_contribution(scene, light, point, rng);
+    }
+    color
+}
 
-        let shadow_intersection = scene.trace(&shadow_ray);
-        let in_light = shadow_intersection.is_none() ||
-                       shadow_intersection.unwrap().distance > light.distance(&hit_point);
+// Reflection of one light, lambertian except for microfacet surfaces. Area
+// lights are sampled with several shadow rays, stratified over their surface.
+pub(crate) fn light_contribution<R: Rng>(scene: &Scene, light: &Light, point: &ShadingPoint, rng: &mut R) -> Color {
+    let mut color  = Color::black();
+    let ShadingPoint { normal: surface_normal, view, .. } = *point;
+
+    for_each_light_sample(scene, light, point, rng, |direction, light_power| {
+        if let SurfaceType::Microfacet { roughness, metallic } = point.material.surface {
+            let surface = Microfacet {
+                color: point.material.color,
+                albedo: point.material.albedo,
+                roughness,
+                metallic,
+            };
+            color = color + surface.brdf(&surface_normal, &view, &direction) * light.color() * light_power;
+            return;
+        }
 
-        let light_intensity = if in_light { light.intensity(&hit_point)} else { 0.0 };
-        let light_power = (surface_normal.dot(&direction_to_light) as f32).max(0.0) * light_intensity;
-        let light_reflected = element.material().albedo / std::f32::consts::PI;
+        let light_reflected = point.material.albedo / std::f32::consts::PI;
 
         let light_color = light.color() * light_power * light_reflected;
 
-        color = color +  element.material().coloration.color(&texture_coord).clone() * light_color;
+        color = color +  point.material.color * light_color;
+    });
 
+    color
+}
+
+// Blinn–Phong highlights of every light. They are kept apart from the diffuse
+// reflection so that the mix of the surface lobes does not scale them down.
+pub(crate) fn highlights<R: Rng>(scene: &Scene, point: &ShadingPoint, rng: &mut R) -> Color {
+    let mut color = Color::black();
+    for light in &scene.lights {
+        color = color + light_highlight(scene, light, point, rng);
     }
+    color
+}
 
-    color.clamp()
-   
+// Highlight of one light; microfacet surfaces have their own.
+pub(crate) fn light_highlight<R: Rng>(scene: &Scene, light: &Light, point: &ShadingPoint, rng: &mut R) -> Color {
+    let material = point.element.material();
+    if !material.has_highlight() || matches!(point.material.surface, SurfaceType::Microfacet { .. }) {
+        return Color::black();
+    }
+    let mut color = Color::black();
+    for_each_light_sample(scene, light, point, rng, |direction, light_power| {
+        color = color + material.highlight(&point.normal, &point.view, &direction) * light.color() * light_power;
+    });
+    color
+}
+
+// Calls `f` with the direction and the power, times the cosine at the hit
+// point, of every sample of the light that is not in shadow.
+fn for_each_light_sample<R: Rng, F: FnMut(Vector3, f32)>(scene: &Scene, light: &Light, point: &ShadingPoint, rng: &mut R, mut f: F) {
+    let ShadingPoint { hit_point, normal: surface_normal, geometric_normal, .. } = *point;
+
+    let light_samples = SamplePattern::Jittered.samples(light.sample_count(), rng);
+    let sample_weight = 1.0 / light_samples.len() as f32;
+
+    for u in light_samples {
+        let sample = light.sample(&hit_point, u);
+        let shadow_ray = Ray::create_shadow(&hit_point, geometric_normal, sample.direction, scene.shadow_bias);
 
+        let shadow_intersection = scene.trace(&shadow_ray);
+        let in_light = shadow_intersection.is_none() ||
+                       shadow_intersection.unwrap().distance > sample.distance;
+        if !in_light {
+            continue;
+        }
+
+        let light_power = (surface_normal.dot(&sample.direction) as f32).max(0.0) * sample.intensity * sample_weight;
+        f(sample.direction, light_power);
+    }
 }
 
 
-fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
+pub(crate) fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let i_dot_n = incident.dot(&normal);
     let mut eta_i = 1.0;
     let mut eta_t = index as f64;
@@ -75,88 +332,336 @@ fn fresnel(incident: Vector3, normal: Vector3, index: f32) -> f64 {
     let sin_t = eta_i / eta_t * (1.0 - i_dot_n * i_dot_n).max(0.0).sqrt();
     if sin_t > 1.0 {
         //Total internal reflection
-        return 1.0;
+        1.0
     } else {
         let cos_t = (1.0 - sin_t * sin_t).max(0.0).sqrt();
-        let cos_i = cos_t.abs();
+        let cos_i = i_dot_n.abs();
         let r_s = ((eta_t * cos_i) - (eta_i * cos_t)) / ((eta_t * cos_i) + (eta_i * cos_t));
         let r_p = ((eta_i * cos_i) - (eta_t * cos_t)) / ((eta_i * cos_i) + (eta_t * cos_t));
-        return (r_s * r_s + r_p * r_p) / 2.0;
+        (r_s * r_s + r_p * r_p) / 2.0
     }
 }
 
 
-fn get_color(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: i32)  -> Color {
-    let hit_point = ray.origin + ray.direction * intersection.distance;
-    let surface_normal = intersection.element.surface_normal(&hit_point);
-    let material = intersection.element.material();
+fn get_color<R: Rng>(scene: &Scene, ray: &Ray, intersection: &Intersection, depth: u32, rng: &mut R)  -> Color {
+    let point = shading_point(ray, intersection);
+    let ShadingPoint { hit_point, normal: facing_normal, geometric_normal: facing_geometric_normal, view, inside, .. } = point;
+    let (surface_normal, geometric_normal) = point.outward_normals();
+    // specular bounces carry on the cone of the incoming ray
+    let cone = ray.cone.at(intersection.distance);
 
-    match  material.surface {
-         SurfaceType::Diffuse =>  shade_diffuse(scene, intersection.element, hit_point, surface_normal),
+    let color = match  point.material.surface {
+         SurfaceType::Diffuse =>  shade_diffuse(scene, &point, rng),
          SurfaceType::Reflective{reflectivity} => {
-            let mut color = shade_diffuse(scene, intersection.element, hit_point, surface_normal);
-            let reflective_ray = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias);
+            let mut color = shade_diffuse(scene, &point, rng);
+            let reflective_ray = Ray::create_reflection(facing_normal, facing_geometric_normal, ray.direction, hit_point, scene.shadow_bias)
+                .with_cone(cone);
             color = color * (1.0 - reflectivity);
-            color = color + trace_ray(scene, &reflective_ray, depth + 1);
+            color = color + trace_ray(scene, &reflective_ray, depth + 1, rng);
             color
          },
-         SurfaceType::Refractive { index, transparency } => {
+         SurfaceType::Refractive { index, transparency, absorption } => {
             let mut refraction_color = Color::black();
             let kr = fresnel(ray.direction, surface_normal, index) as f32;
-            let surface_color = material.coloration
-                .color(&intersection.element.texture_coords(&hit_point));
 
             if kr < 1.0 {
                 let transmission_ray =
-                    Ray::create_transmission(surface_normal, ray.direction, hit_point, scene.shadow_bias, index);
-                if transmission_ray.is_some() {
-                    refraction_color = trace_ray(scene, &transmission_ray.unwrap(), depth + 1);
-                } 
+                    Ray::create_transmission(surface_normal, geometric_normal, ray.direction, hit_point, scene.shadow_bias, index);
+                if let Some(transmission_ray) = transmission_ray.map(|ray| ray.with_cone(cone)) {
+                    refraction_color = trace_ray(scene, &transmission_ray, depth + 1, rng);
+                }
+            }
+
+            let reflective_ray = Ray::create_reflection(facing_normal, facing_geometric_normal, ray.direction, hit_point, scene.shadow_bias)
+                .with_cone(cone)
+                .with_medium(ray.in_medium);
+            let reflection_color = trace_ray(scene, &reflective_ray, depth + 1, rng);
+            let mut color = (reflection_color * kr + refraction_color * (1.0 - kr)) * transparency;
+
+            if transparency < 1.0 {
+                let diffuse_color = shade_diffuse(scene, &point, rng);
+                color = color + diffuse_color * (1.0 - transparency);
             }
 
-            let reflective_ray = Ray::create_reflection(surface_normal, ray.direction, hit_point, scene.shadow_bias);
-            let reflection_color = trace_ray(scene, &reflective_ray, depth + 1);
-            let mut color = reflection_color * kr + refraction_color * (1.0 - kr);
-            color = color * transparency * surface_color;
+            // open surfaces are also hit from behind by rays that never
+            // travelled through the material
+            if inside && ray.in_medium {
+                color = color * transmittance(point.material.color, absorption, intersection.distance);
+            }
 
             color
-         }
-    }
+         },
+         SurfaceType::Microfacet { roughness, metallic } => {
+            let surface = Microfacet {
+                color: point.material.color,
+                albedo: point.material.albedo,
+                roughness,
+                metallic,
+            };
+
+            // lights are shaded with the full BRDF, the rest of the scene
+            // through one glossy reflection sampled from the specular lobe
+            let mut color = direct_lighting(scene, &point, rng);
+            if let Some((direction, weight)) = surface.sample_specular(&facing_normal, &view, rng) {
+                let reflective_ray = Ray::new(hit_point + facing_geometric_normal * scene.shadow_bias, direction);
+                color = color + trace_ray(scene, &reflective_ray, depth + 1, rng) * weight;
+            }
+            color
+         },
+    };
+    color + highlights(scene, &point, rng)
 }
 
-fn trace_ray(scene: &Scene, ray: &Ray, depth: i32) -> Color {
+fn trace_ray<R: Rng>(scene: &Scene, ray: &Ray, depth: u32, rng: &mut R) -> Color {
 
-    if depth >= MAX_RECURSION_DEPTH {
+    if depth >= scene.max_recursion_depth {
         return Color::black();
     }
 
-    let intersection = scene.trace(&ray);
-    intersection.map(|i| get_color(&scene, &ray, &i, depth))
+    let intersection = scene.trace(ray);
+    intersection.map(|i| get_color(scene, ray, &i, depth, rng))
             .unwrap_or(Color::black())
 } 
 
-pub fn render(scene: &Scene) -> DynamicImage{
+struct Tile {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+fn tiles(width: u32, height: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y in (0..height).step_by(TILE_SIZE as usize) {
+        for x in (0..width).step_by(TILE_SIZE as usize) {
+            tiles.push(Tile {
+                x,
+                y,
+                width: TILE_SIZE.min(width - x),
+                height: TILE_SIZE.min(height - y),
+            });
+        }
+    }
+    tiles
+}
+
+// Weighted sums of the samples of a pixel and of its filtered AOVs, with the
+// sum of the weights and the number of samples. Unfiltered AOVs hold their
+// value at the pixel center.
+struct Pixel {
+    color: Color,
+    weight: f32,
+    count: u32,
+    aovs: Vec<Color>,
+}
+
+// The samples of a pixel cover the support of the reconstruction filter
+// around the pixel center and are weighted by it.
+fn render_pixel(scene: &Scene, x: u32, y: u32) -> Pixel {
     let (width, height) = scene.dimension();
-    let mut image = DynamicImage::new_rgb8(width, height);
+    let radius = scene.filter.radius();
+
+    // seeded per pixel so the image does not depend on how tiles are scheduled
+    let seed = ((y as u64) << 32) | (x as u64);
+    let mut rng = SmallRng::seed_from_u64(seed);
+    // AOVs draw from their own sequence so that they leave the image unchanged
+    let mut aov_rng = SmallRng::seed_from_u64(!seed);
+
+    let mut color = Color::black();
+    let mut total_weight = 0.0;
+    let mut count = 0;
+    let mut aovs = vec![Color::black(); scene.aovs.len()];
+    let cone = RayCone::new(0.0, scene.camera.pixel_spread(height));
+    for (sx, sy) in scene.sample_pattern.samples(scene.samples, &mut rng) {
+        let dx = (2.0 * sx - 1.0) * radius;
+        let dy = (2.0 * sy - 1.0) * radius;
+        let weight = scene.filter.evaluate(dx, dy) as f32;
+
+        let xx = ((x as f64) + 0.5 + dx) / (width as f64);
+        let yy = ((y as f64) + 0.5 + dy) / (height as f64);
+        let ray = scene.camera.get_ray(xx, yy, (rng.random(), rng.random())).with_cone(cone);
+
+        let sample = match scene.integrator {
+            Integrator::Whitted => trace_ray(scene, &ray, 0, &mut rng),
+            Integrator::PathTracing => integrator::trace_path(scene, &ray, &mut rng),
+        };
+        color = color + sample * weight;
+        total_weight += weight;
+        count += 1;
+
+        if !scene.aovs.is_empty() {
+            for (sum, value) in aovs.iter_mut().zip(aov::evaluate(scene, &ray, true, &mut aov_rng)) {
+                *sum = *sum + value * weight;
+            }
+        }
+    }
+
+    // unfiltered AOVs see the pixel center through the center of the lens
+    if scene.aovs.iter().any(|aov| !aov.is_filtered()) {
+        let xx = ((x as f64) + 0.5) / (width as f64);
+        let yy = ((y as f64) + 0.5) / (height as f64);
+        let ray = scene.camera.get_ray(xx, yy, (0.5, 0.5)).with_cone(cone);
+        for (sum, value) in aovs.iter_mut().zip(aov::evaluate(scene, &ray, false, &mut aov_rng)) {
+            *sum = *sum + value;
+        }
+    }
+
+    Pixel { color, weight: total_weight, count, aovs }
+}
+
+fn render_tile(scene: &Scene, tile: &Tile) -> Vec<Pixel> {
+    let mut pixels = Vec::with_capacity((tile.width * tile.height) as usize);
+    for y in tile.y..tile.y + tile.height {
+        for x in tile.x..tile.x + tile.width {
+            pixels.push(render_pixel(scene, x, y));
+        }
+    }
+    pixels
+}
 
+// Every pixel is computed independently, so the tiles can be rendered in any
+// order across the thread pool and still produce the same image.
+pub fn render(scene: &Scene) -> Framebuffer {
+    let (width, height) = scene.dimension();
+    let mut framebuffer = Framebuffer::new(width, height).with_aovs(scene.aovs.clone());
+
+    let tiles = tiles(width, height);
+    let pool = ThreadPoolBuilder::new()
+        .num_threads(scene.threads)
+        .build()
+        .expect("could not create render thread pool");
+    let rendered: Vec<Vec<Pixel>> = pool.install(|| {
+        tiles.par_iter().map(|tile| render_tile(scene, tile)).collect()
+    });
+
+    for (tile, pixels) in tiles.iter().zip(rendered) {
+        for (i, pixel) in pixels.into_iter().enumerate() {
+            let x = tile.x + (i as u32) % tile.width;
+            let y = tile.y + (i as u32) / tile.width;
+            framebuffer.accumulate(x, y, pixel.color, pixel.weight, pixel.count, &pixel.aovs);
+        }
+    }
 
+    framebuffer
+}
 
-    for x in 0..scene.width {
-        for y in 0..scene.height {
-            let xx = ((x as f64) + 0.5)/ (width as f64);
-            let yy = ((y as f64) + 0.5)/ (height as f64);
-            let ray = scene.camera.get_ray(xx, yy);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::raytracer::element::{Plane, Sphere, Triangle};
+    use crate::raytracer::light::{SphereLight, SphericalLight};
+    use crate::raytracer::material::{Coloration, Material};
+
+    // Color at the center of a render of a triangle facing the camera with
+    // its front or back face, lit from the camera.
+    fn render_triangle(front: bool) -> Color {
+        let (v1, v2) = (Point::new(0.0, 1.0, -3.0), Point::new(1.0, -1.0, -3.0));
+        let (v1, v2) = if front { (v2, v1) } else { (v1, v2) };
+        let triangle = Element::Triangle(Triangle { v0: Point::new(-1.0, -1.0, -3.0), v1, v2, material: Material::diffuse(Color::new(1.0, 1.0, 1.0), 1.0) });
+        let light = Light::SphericalLight(SphericalLight::new(Point::zero(), Color::new(1.0, 1.0, 1.0), 100.0));
+        let scene = Scene::new(3, 3, vec![triangle], vec![light]);
+        render(&scene).color(1, 1)
+    }
 
-            let color = trace_ray(scene, &ray, 0);
+    #[test]
+    fn shades_both_faces_of_triangles() {
+        let front = render_triangle(true);
+        let back = render_triangle(false);
+        assert!(front.red > 0.0);
+        assert!((front.red - back.red).abs() < 1e-6, "front {:?}, back {:?}", front, back);
+    }
 
-            image.put_pixel(x, y, color.to_rgba());
+    #[test]
+    fn renders_do_not_depend_on_the_number_of_threads() {
+        let render_with = |threads| {
+            let elements = vec![
+                Element::Sphere(Sphere { center: Point::new(0.0, 0.0, -5.0), radius: 1.0, material: Material::diffuse(Color::new(1.0, 0.5, 0.5), 0.5) }),
+                Element::Plane(Plane { origin: Point::new(0.0, -1.0, 0.0), normal: Vector3::new(0.0, -1.0, 0.0), material: Material::diffuse(Color::new(1.0, 1.0, 1.0), 0.5) }),
+            ];
+            let light = Light::SphereLight(SphereLight::new(Point::new(2.0, 3.0, -3.0), 0.5, Color::new(1.0, 1.0, 1.0), 1000.0, 4));
+            // several tiles, some of them partial
+            let mut scene = Scene::new(40, 70, elements, vec![light]);
+            scene.integrator = Integrator::PathTracing;
+            scene.samples = 4;
+            scene.aovs = vec![Aov::Depth, Aov::Albedo];
+            scene.threads = threads;
+            render(&scene)
+        };
+
+        let single = render_with(1);
+        let many = render_with(4);
+        for (x, y) in (0..40).flat_map(|y| (0..70).map(move |x| (x, y))) {
+            let pixel = |framebuffer: &Framebuffer| {
+                let [color, depth, albedo] = [framebuffer.color(x, y), framebuffer.aov_color(0, x, y), framebuffer.aov_color(1, x, y)];
+                [color, depth, albedo].map(|c| [c.red.to_bits(), c.green.to_bits(), c.blue.to_bits()])
+            };
+            assert_eq!(pixel(&single), pixel(&many), "pixel ({}, {})", x, y);
+        }
+    }
 
-            // let intersection = scene.trace(&ray);
-            // let color = intersection.map(|i| get_color(&scene, &ray, &i).to_rgba())
-            //     .unwrap_or(Color::new(0.2, 0.2, 0.6).to_rgba());
-            
+    #[test]
+    fn unfiltered_aovs_are_not_blended_across_edges() {
+        let sphere = Element::Sphere(Sphere { center: Point::new(0.0, 0.0, -5.0), radius: 1.0, material: Material::diffuse(Color::new(1.0, 1.0, 1.0), 1.0) });
+        let mut scene = Scene::new(9, 9, vec![sphere], Vec::new()).with_object_ids(vec![7]).unwrap();
+        scene.samples = 16;
+        scene.filter = Filter::Mitchell;
+        scene.aovs = vec![Aov::ObjectId, Aov::Depth];
+
+        let framebuffer = render(&scene);
+        for (x, y) in (0..9).flat_map(|y| (0..9).map(move |x| (x, y))) {
+            let id = framebuffer.aov_color(0, x, y).red;
+            assert!(id == 0.0 || id == 7.0, "object id {} at ({}, {})", id, x, y);
         }
+        assert!((framebuffer.aov_color(1, 4, 4).red - 4.0).abs() < 1e-6);
+        assert!(Scene::new(9, 9, Vec::new(), Vec::new()).with_object_ids(vec![1]).is_err());
+    }
+
+    #[test]
+    fn mirrors_keep_their_highlights() {
+        // the highlight of a light at the camera sits at the center of the sphere
+        let center = |reflectivity, specular| {
+            let material = Material {
+                surface: SurfaceType::Reflective { reflectivity },
+                specular_color: Color::new(specular, specular, specular),
+                specular_exponent: 50.0,
+                ..Material::diffuse(Color::new(1.0, 1.0, 1.0), 1.0)
+            };
+            let sphere = Element::Sphere(Sphere { center: Point::new(0.0, 0.0, -5.0), radius: 1.0, material });
+            let light = Light::SphericalLight(SphericalLight::new(Point::zero(), Color::new(1.0, 1.0, 1.0), 100.0));
+            render(&Scene::new(3, 3, vec![sphere], vec![light])).color(1, 1).red
+        };
+        let highlight = center(1.0, 1.0);
+        assert!(highlight > 0.0);
+        // the reflectivity only takes away the diffuse reflection
+        assert!((center(0.0, 1.0) - center(0.0, 0.0) - highlight).abs() < 1e-4 * highlight);
+        assert!((center(0.5, 1.0) - center(0.5, 0.0) - highlight).abs() < 1e-4 * highlight);
     }
 
-    image
-}
\ No newline at end of file
+    #[test]
+    fn open_refractive_surfaces_only_absorb_rays_refracted_into_them() {
+        // a wall lit from between it and a pane that bends nothing and
+        // reflects nothing, but would absorb a lot over the distance from the camera
+        let center = |pane: Option<bool>| {
+            let wall = Element::Triangle(Triangle {
+                v0: Point::new(-5.0, -5.0, -6.0), v1: Point::new(5.0, -5.0, -6.0), v2: Point::new(0.0, 5.0, -6.0), material: Material::diffuse(Color::new(1.0, 1.0, 1.0), 1.0),
+            });
+            let mut elements = vec![wall];
+            if let Some(front) = pane {
+                let (v1, v2) = (Point::new(1.0, -1.0, -3.0), Point::new(0.0, 1.0, -3.0));
+                let (v1, v2) = if front { (v1, v2) } else { (v2, v1) };
+                let material = Material {
+                    coloration: Coloration::Color(Color::new(0.2, 0.2, 0.2)),
+                    surface: SurfaceType::Refractive { index: 1.0, transparency: 1.0, absorption: 1.0 },
+                    ..Material::diffuse(Color::new(1.0, 1.0, 1.0), 1.0)
+                };
+                elements.push(Element::Triangle(Triangle { v0: Point::new(-1.0, -1.0, -3.0), v1, v2, material }));
+            }
+            let light = Light::SphericalLight(SphericalLight::new(Point::new(0.0, 0.0, -4.0), Color::new(1.0, 1.0, 1.0), 100.0));
+            render(&Scene::new(3, 3, elements, vec![light])).color(1, 1).red
+        };
+        let bare = center(None);
+        assert!(bare > 0.0);
+        assert!((center(Some(true)) - bare).abs() < 1e-4 * bare);
+        assert!((center(Some(false)) - bare).abs() < 1e-4 * bare);
+    }
+}
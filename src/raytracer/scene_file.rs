@@ -0,0 +1,1077 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::de::{self, Deserializer, MapAccess, Visitor};
+use serde::de::value::MapAccessDeserializer;
+use serde::Deserialize;
+use toml::Spanned;
+
+use super::camera::Camera;
+use super::element::{Element, Instance, Mesh, Object, Plane, Sphere, Triangle};
+use super::geometry::{Matrix4, Point, Transform, Vector3};
+use super::ies;
+use super::light::{DirectionalLight, DiskLight, Light, RectangleLight, SphereLight, SphericalLight, SpotLight};
+use super::obj;
+use super::procedural::{Domain, Pattern, Procedural};
+use super::bump::{BumpMap, NormalMap};
+use super::material::{AddressMode, Channel, Color, ColorSpace, Coloration, DEFAULT_ABSORPTION, Material, Parameter, ParameterMap, SurfaceType, Texture, TextureCoords, TextureFilter, UvTransform};
+use super::scene::Scene;
+
+// Declarative description of a scene, as read from a TOML scene file.
+//
+// Textures and materials are declared once under a name and referenced by
+// that name from materials and elements. Relative texture paths are resolved
+// against the directory containing the scene file. Objects are elements that
+// are not rendered by themselves but placed, any number of times, by
+// `instance` elements.
+//
+// Entries keep their span in the file so that errors found while building the
+// scene report the line of the entry at fault.
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDescription {
+    pub camera: Spanned<CameraDescription>,
+    #[serde(default)]
+    pub textures: HashMap<String, Spanned<TextureDescription>>,
+    #[serde(default)]
+    pub materials: HashMap<String, Spanned<MaterialDescription>>,
+    #[serde(default)]
+    pub objects: HashMap<String, Spanned<ElementDescription>>,
+    #[serde(default)]
+    pub elements: Vec<Spanned<ElementDescription>>,
+    #[serde(default)]
+    pub lights: Vec<Spanned<LightDescription>>,
+    #[serde(skip)]
+    source: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub width: u32,
+    pub height: u32,
+    #[serde(default)]
+    pub look_from: Option<[f64; 3]>,
+    #[serde(default)]
+    pub look_at: Option<[f64; 3]>,
+    #[serde(default)]
+    pub vup: Option<[f64; 3]>,
+    #[serde(default)]
+    pub vfov: Option<f64>,
+    #[serde(default)]
+    pub aperture: Option<f64>,
+    #[serde(default)]
+    pub focus_distance: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MaterialDescription {
+    pub coloration: ColorationDescription,
+    pub albedo: ParameterDescription,
+    #[serde(default)]
+    pub surface: SurfaceDescription,
+    #[serde(default)]
+    pub specular_color: [f32; 3],
+    #[serde(default)]
+    pub specular_exponent: f32,
+    #[serde(default)]
+    pub normal_map: Option<NormalMapDescription>,
+    #[serde(default)]
+    pub bump_map: Option<BumpMapDescription>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NormalMapDescription {
+    pub texture: TextureReference,
+    #[serde(default = "default_strength")]
+    pub strength: f32,
+}
+
+impl MaterialDescription {
+
+    fn parameters(&self) -> Vec<&ParameterDescription> {
+        let mut parameters = vec![&self.albedo];
+        match &self.surface {
+            SurfaceDescription::Diffuse => {},
+            SurfaceDescription::Reflective { reflectivity } => parameters.push(reflectivity),
+            SurfaceDescription::Refractive { index, transparency, .. } => parameters.extend([index, transparency]),
+            SurfaceDescription::Microfacet { roughness, metallic } => parameters.extend([roughness, metallic]),
+        }
+        parameters
+    }
+}
+
+fn default_strength() -> f32 {
+    1.0
+}
+
+// `scale` is the height, in scene units, of a luminance of 1.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BumpMapDescription {
+    pub height: ColorationDescription,
+    pub scale: f32,
+}
+
+// A texture is either its path, an image filtered trilinearly and repeated,
+// or a table giving the path with the filter, the address mode and the color
+// space. Without a color space, textures read by normal, bump or parameter
+// maps are linear and the others sRGB; maps cannot read sRGB textures.
+#[derive(Debug)]
+pub enum TextureDescription {
+    Path(PathBuf),
+    Table(TextureTable),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TextureTable {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub filter: FilterDescription,
+    #[serde(default)]
+    pub address: AddressDescription,
+    #[serde(default)]
+    pub color_space: Option<ColorSpaceDescription>,
+}
+
+// Not untagged, which would report any mistake in the table as not matching
+// either form rather than naming the field at fault.
+impl<'de> Deserialize<'de> for TextureDescription {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct TextureVisitor;
+
+        impl<'de> Visitor<'de> for TextureVisitor {
+            type Value = TextureDescription;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a path or a table with a path")
+            }
+
+            fn visit_str<E: de::Error>(self, path: &str) -> Result<TextureDescription, E> {
+                Ok(TextureDescription::Path(PathBuf::from(path)))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<TextureDescription, A::Error> {
+                TextureTable::deserialize(MapAccessDeserializer::new(map)).map(TextureDescription::Table)
+            }
+        }
+
+        deserializer.deserialize_any(TextureVisitor)
+    }
+}
+
+// Normal maps and other data are stored linearly, colors in sRGB.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorSpaceDescription {
+    Srgb,
+    Linear,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterDescription {
+    Nearest,
+    Bilinear,
+    #[default]
+    Trilinear,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AddressDescription {
+    #[default]
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+    Border([f32; 3]),
+}
+
+// A texture of a coloration is either its name or a table giving the name
+// with a transform of the texture coordinates: scaled, rotated by degrees,
+// then offset.
+#[derive(Debug)]
+pub enum TextureReference {
+    Name(String),
+    Transformed(TransformedTexture),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformedTexture {
+    pub name: String,
+    #[serde(default = "default_uv_scale")]
+    pub scale: [f32; 2],
+    #[serde(default)]
+    pub rotate: f32,
+    #[serde(default)]
+    pub offset: [f32; 2],
+}
+
+impl<'de> Deserialize<'de> for TextureReference {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ReferenceVisitor;
+
+        impl<'de> Visitor<'de> for ReferenceVisitor {
+            type Value = TextureReference;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a texture name or a table with a name")
+            }
+
+            fn visit_str<E: de::Error>(self, name: &str) -> Result<TextureReference, E> {
+                Ok(TextureReference::Name(name.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<TextureReference, A::Error> {
+                TransformedTexture::deserialize(MapAccessDeserializer::new(map)).map(TextureReference::Transformed)
+            }
+        }
+
+        deserializer.deserialize_any(ReferenceVisitor)
+    }
+}
+
+// Any coloration looked up through a transform of the texture coordinates,
+// as for textures. Patterns over positions are left unchanged.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TransformDescription {
+    pub coloration: ColorationDescription,
+    #[serde(default = "default_uv_scale")]
+    pub scale: [f32; 2],
+    #[serde(default)]
+    pub rotate: f32,
+    #[serde(default)]
+    pub offset: [f32; 2],
+}
+
+impl TextureReference {
+
+    fn name(&self) -> &str {
+        match self {
+            TextureReference::Name(name) => name,
+            TextureReference::Transformed(TransformedTexture { name, .. }) => name,
+        }
+    }
+}
+
+fn default_uv_scale() -> [f32; 2] {
+    [1.0, 1.0]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase", deny_unknown_fields)]
+pub enum ColorationDescription {
+    Color([f32; 3]),
+    Texture(TextureReference),
+    Pattern(Box<PatternDescription>),
+    Transform(Box<TransformDescription>),
+}
+
+// A procedural pattern blending two colorations. Checkers, stripes and
+// gradients default to the uv domain and the other patterns, solid ones, to
+// the position domain.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PatternDescription {
+    #[serde(rename = "type")]
+    pub kind: PatternKind,
+    #[serde(default)]
+    pub domain: Option<DomainDescription>,
+    #[serde(default = "default_pattern_scale")]
+    pub scale: f64,
+    #[serde(default)]
+    pub octaves: Option<u32>,
+    #[serde(default)]
+    pub distortion: Option<f64>,
+    pub colors: [ColorationDescription; 2],
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternKind {
+    Checker,
+    Stripes,
+    Gradient,
+    Noise,
+    Turbulence,
+    Marble,
+    Wood,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DomainDescription {
+    Uv,
+    Position,
+}
+
+const DEFAULT_OCTAVES: u32 = 4;
+const DEFAULT_DISTORTION: f64 = 1.0;
+
+impl ColorationDescription {
+
+    // Adds the names of the textures the coloration reads to `names`.
+    fn texture_names<'a>(&'a self, names: &mut HashSet<&'a str>) {
+        match self {
+            ColorationDescription::Color(_) => {},
+            ColorationDescription::Texture(reference) => {
+                names.insert(reference.name());
+            },
+            ColorationDescription::Pattern(pattern) => {
+                for coloration in &pattern.colors {
+                    coloration.texture_names(names);
+                }
+            },
+            ColorationDescription::Transform(transform) => transform.coloration.texture_names(names),
+        }
+    }
+}
+
+fn default_pattern_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum SurfaceDescription {
+    #[default]
+    Diffuse,
+    Reflective { reflectivity: ParameterDescription },
+    Refractive {
+        index: ParameterDescription,
+        transparency: ParameterDescription,
+        #[serde(default = "default_absorption")]
+        absorption: f32,
+    },
+    Microfacet {
+        roughness: ParameterDescription,
+        #[serde(default)]
+        metallic: ParameterDescription,
+    },
+}
+
+// A scalar material parameter is either a number or a table giving a map,
+// one channel of which scales `value` over the surface.
+#[derive(Debug)]
+pub enum ParameterDescription {
+    Value(f32),
+    Map(ParameterMapDescription),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ParameterMapDescription {
+    #[serde(default = "default_parameter_value")]
+    pub value: f32,
+    pub map: ColorationDescription,
+    #[serde(default)]
+    pub channel: ChannelDescription,
+}
+
+impl<'de> Deserialize<'de> for ParameterDescription {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ParameterVisitor;
+
+        impl<'de> Visitor<'de> for ParameterVisitor {
+            type Value = ParameterDescription;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a number or a table with a map")
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<ParameterDescription, E> {
+                Ok(ParameterDescription::Value(value as f32))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<ParameterDescription, E> {
+                Ok(ParameterDescription::Value(value as f32))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<ParameterDescription, E> {
+                Ok(ParameterDescription::Value(value as f32))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<ParameterDescription, A::Error> {
+                ParameterMapDescription::deserialize(MapAccessDeserializer::new(map)).map(ParameterDescription::Map)
+            }
+        }
+
+        deserializer.deserialize_any(ParameterVisitor)
+    }
+}
+
+impl Default for ParameterDescription {
+    fn default() -> Self {
+        ParameterDescription::Value(0.0)
+    }
+}
+
+fn default_parameter_value() -> f32 {
+    1.0
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChannelDescription {
+    Red,
+    Green,
+    Blue,
+    #[default]
+    Luminance,
+}
+
+fn default_absorption() -> f32 {
+    DEFAULT_ABSORPTION
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum ElementDescription {
+    Sphere { center: [f64; 3], radius: f64, material: String },
+    Plane { origin: [f64; 3], normal: [f64; 3], material: String },
+    Triangle { vertices: [[f64; 3]; 3], material: String },
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        #[serde(default)]
+        normals: Vec<[f64; 3]>,
+        #[serde(default)]
+        uvs: Vec<[f32; 2]>,
+        indices: Vec<[usize; 3]>,
+        material: String,
+    },
+    // Wavefront OBJ file; `material` overrides the materials of its MTL files.
+    Obj {
+        path: PathBuf,
+        #[serde(default)]
+        material: Option<String>,
+    },
+    // Object scaled, then rotated about the x, y and z axes in that order
+    // (degrees), then translated.
+    Instance {
+        object: String,
+        #[serde(default)]
+        translate: [f64; 3],
+        #[serde(default)]
+        rotate: [f64; 3],
+        #[serde(default = "default_scale")]
+        scale: [f64; 3],
+    },
+}
+
+fn default_scale() -> [f64; 3] {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum LightDescription {
+    Directional { direction: [f64; 3], color: [f32; 3], intensity: f32 },
+    Spherical { position: [f64; 3], color: [f32; 3], intensity: f32 },
+    Spot {
+        position: [f64; 3],
+        direction: [f64; 3],
+        inner_angle: f64, // degrees from the axis
+        outer_angle: f64,
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default)]
+        profile: Option<PathBuf>, // IES file
+    },
+    Rectangle {
+        position: [f64; 3],
+        edge_u: [f64; 3],
+        edge_v: [f64; 3],
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+    Disk {
+        position: [f64; 3],
+        normal: [f64; 3],
+        radius: f64,
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+    Sphere {
+        position: [f64; 3],
+        radius: f64,
+        color: [f32; 3],
+        intensity: f32,
+        #[serde(default = "default_light_samples")]
+        samples: u32,
+    },
+}
+
+fn default_light_samples() -> u32 {
+    16
+}
+
+impl SceneDescription {
+
+    pub fn from_file(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("{}: could not read scene file: {}", path.display(), e))?;
+        Self::parse(&source).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut description: Self = toml::from_str(source).map_err(|e| e.to_string())?;
+        description.source = source.to_string();
+        Ok(description)
+    }
+
+    // Line of the scene file on which a spanned entry starts.
+    fn line(&self, span: Range<usize>) -> usize {
+        self.source.get(..span.start).unwrap_or_default().matches('\n').count() + 1
+    }
+
+    // Builds the scene, resolving relative texture paths against `base_dir`.
+    pub fn build(&self, base_dir: &Path) -> Result<Scene, String> {
+        let camera = self.camera.get_ref();
+        let camera_line = self.line(self.camera.span());
+        if camera.width == 0 || camera.height == 0 {
+            return Err(format!("line {}: camera: width and height must be greater than zero", camera_line));
+        }
+
+        let aspect_ratio = (camera.width as f64) / (camera.height as f64);
+        let mut scene_camera = Camera::default_with_aspect_ratio(aspect_ratio);
+        if let Some(look_from) = &camera.look_from {
+            scene_camera = scene_camera.look_from(to_point(look_from));
+        }
+        if let Some(look_at) = &camera.look_at {
+            scene_camera = scene_camera.look_at(to_point(look_at));
+        }
+        if let Some(vup) = &camera.vup {
+            scene_camera = scene_camera.vup(to_vector(vup));
+        }
+        if let Some(vfov) = camera.vfov {
+            scene_camera = scene_camera.vfov(vfov);
+        }
+        if let Some(aperture) = camera.aperture {
+            scene_camera = scene_camera.aperture(aperture);
+        }
+        if let Some(focus_distance) = camera.focus_distance {
+            scene_camera = scene_camera.focus_distance(focus_distance);
+        }
+        scene_camera.validate().map_err(|e| format!("line {}: camera.{}", camera_line, e))?;
+
+        let data_textures = self.data_textures();
+        let mut textures = HashMap::new();
+        for (name, description) in &self.textures {
+            let line = self.line(description.span());
+            let (path, filter, address, color_space) = match description.get_ref() {
+                TextureDescription::Path(path) => (path, FilterDescription::default(), AddressDescription::default(), None),
+                TextureDescription::Table(TextureTable { path, filter, address, color_space }) =>
+                    (path, *filter, *address, *color_space),
+            };
+            let filter = match filter {
+                FilterDescription::Nearest => TextureFilter::Nearest,
+                FilterDescription::Bilinear => TextureFilter::Bilinear,
+                FilterDescription::Trilinear => TextureFilter::Trilinear,
+            };
+            let address_mode = match address {
+                AddressDescription::Repeat => AddressMode::Repeat,
+                AddressDescription::MirroredRepeat => AddressMode::MirroredRepeat,
+                AddressDescription::ClampToEdge => AddressMode::ClampToEdge,
+                AddressDescription::Border(color) => AddressMode::Border(to_color(&color)),
+            };
+            let color_space = match (color_space, data_textures.contains(name.as_str())) {
+                (Some(ColorSpaceDescription::Srgb), true) => return Err(format!(
+                    "line {}: textures.{}.color_space: read as data by a map, which needs a linear texture", line, name)),
+                (Some(ColorSpaceDescription::Linear), _) | (None, true) => ColorSpace::Linear,
+                (Some(ColorSpaceDescription::Srgb), false) | (None, false) => ColorSpace::Srgb,
+            };
+            let path = base_dir.join(path);
+            let texture = Texture::load_texture(&path.to_string_lossy(), color_space)
+                .map_err(|e| format!("line {}: textures.{}: {}", line, name, e))?
+                .with_filter(filter)
+                .with_address_mode(address_mode);
+            textures.insert(name.as_str(), texture);
+        }
+
+        let mut objects = HashMap::new();
+        for (name, object) in &self.objects {
+            let elements = self.build_element(object.get_ref(), base_dir, &textures)
+                .map_err(|e| format!("line {}: objects.{}.{}", self.line(object.span()), name, e))?;
+            objects.insert(name.as_str(), Arc::new(Object::new(elements)));
+        }
+
+        // every element built from elements[i], such as the faces of a mesh,
+        // gets object id i + 1
+        let mut elements = Vec::new();
+        let mut element_ids = Vec::new();
+        let mut instances = Vec::new();
+        let mut instance_ids = Vec::new();
+        for (i, element) in self.elements.iter().enumerate() {
+            let key = format!("line {}: elements[{}]", self.line(element.span()), i);
+            if let ElementDescription::Instance { object, translate, rotate, scale } = element.get_ref() {
+                let object = objects
+                    .get(object.as_str())
+                    .ok_or_else(|| format!("{}.object: unknown object `{}`", key, object))?;
+                let transform = build_transform(translate, rotate, scale).map_err(|e| format!("{}.{}", key, e))?;
+                instances.push(Instance::new(object.clone(), transform));
+                instance_ids.push(i as u32 + 1);
+                continue;
+            }
+            let built = self.build_element(element.get_ref(), base_dir, &textures)
+                .map_err(|e| format!("{}.{}", key, e))?;
+            element_ids.extend(std::iter::repeat_n(i as u32 + 1, built.len()));
+            elements.extend(built);
+        }
+        let object_ids = element_ids.into_iter().chain(instance_ids).collect();
+
+        let lights = self.lights
+            .iter()
+            .enumerate()
+            .map(|(i, light)| {
+                build_light(light.get_ref(), base_dir)
+                    .map_err(|e| format!("line {}: lights[{}].{}", self.line(light.span()), i, e))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Scene::new(camera.height, camera.width, elements, lights)
+            .with_camera(scene_camera)
+            .with_instances(instances)
+            .with_object_ids(object_ids)
+    }
+
+    // Meshes expand to one element per face. Instances are not elements and
+    // are built with the objects they place.
+    fn build_element(&self, element: &ElementDescription, base_dir: &Path, textures: &HashMap<&str, Texture>) -> Result<Vec<Element>, String> {
+        match element {
+            ElementDescription::Sphere { center, radius, material } => {
+                if *radius <= 0.0 {
+                    return Err(format!("radius: must be greater than zero, got {}", radius));
+                }
+                Ok(vec![Element::Sphere(Sphere {
+                    center: to_point(center),
+                    radius: *radius,
+                    material: self.build_material(material, textures)?,
+                })])
+            },
+            ElementDescription::Plane { origin, normal, material } => {
+                let normal = to_vector(normal);
+                if normal.length() == 0.0 {
+                    return Err(String::from("normal: must not be the zero vector"));
+                }
+                Ok(vec![Element::Plane(Plane {
+                    origin: to_point(origin),
+                    normal,
+                    material: self.build_material(material, textures)?,
+                })])
+            },
+            ElementDescription::Triangle { vertices, material } => {
+                Ok(vec![Element::Triangle(Triangle {
+                    v0: to_point(&vertices[0]),
+                    v1: to_point(&vertices[1]),
+                    v2: to_point(&vertices[2]),
+                    material: self.build_material(material, textures)?,
+                })])
+            },
+            ElementDescription::Mesh { positions, normals, uvs, indices, material } => {
+                let mesh = Mesh {
+                    positions: positions.iter().map(to_point).collect(),
+                    normals: normals.iter().map(|n| to_vector(n).normalize()).collect(),
+                    uvs: uvs.iter().map(|uv| TextureCoords { x: uv[0], y: uv[1] }).collect(),
+                    indices: indices.clone(),
+                    material: self.build_material(material, textures)?,
+                };
+                mesh.validate()?;
+                Ok(mesh.into_elements())
+            },
+            ElementDescription::Obj { path, material } => {
+                let mut meshes = obj::load_obj(&base_dir.join(path)).map_err(|e| format!("path: {}", e))?;
+                if let Some(material) = material {
+                    for mesh in meshes.iter_mut() {
+                        mesh.material = self.build_material(material, textures)?;
+                    }
+                }
+                Ok(meshes.into_iter().flat_map(Mesh::into_elements).collect())
+            },
+            ElementDescription::Instance { .. } => Err(String::from("type: an object cannot be an instance")),
+        }
+    }
+
+    // Names of the textures read as data by the normal, bump and parameter
+    // maps of the materials.
+    fn data_textures(&self) -> HashSet<&str> {
+        let mut names = HashSet::new();
+        for material in self.materials.values() {
+            let material = material.get_ref();
+            for parameter in material.parameters() {
+                if let ParameterDescription::Map(ParameterMapDescription { map, .. }) = parameter {
+                    map.texture_names(&mut names);
+                }
+            }
+            if let Some(normal_map) = &material.normal_map {
+                names.insert(normal_map.texture.name());
+            }
+            if let Some(bump_map) = &material.bump_map {
+                bump_map.height.texture_names(&mut names);
+            }
+        }
+        names
+    }
+
+    fn build_material(&self, name: &str, textures: &HashMap<&str, Texture>) -> Result<Material, String> {
+        let description = self.materials
+            .get(name)
+            .ok_or_else(|| format!("material: unknown material `{}`", name))?;
+        // errors in the material point to its own entry
+        let material_key = format!("line {}: materials.{}", self.line(description.span()), name);
+        let description = description.get_ref();
+
+        let coloration = build_coloration(&description.coloration, &format!("{}.coloration", material_key), textures)
+            .map_err(|e| format!("material: {}", e))?;
+
+        // constants of the parameters, collecting their maps
+        let mut parameter_maps = Vec::new();
+        let mut parameter = |description: &ParameterDescription, parameter: Parameter, key: &str| {
+            match description {
+                ParameterDescription::Value(value) => Ok(*value),
+                ParameterDescription::Map(ParameterMapDescription { value, map, channel }) => {
+                    let coloration = build_coloration(map, &format!("{}.{}.map", material_key, key), textures)
+                        .map_err(|e| format!("material: {}", e))?;
+                    let channel = match channel {
+                        ChannelDescription::Red => Channel::Red,
+                        ChannelDescription::Green => Channel::Green,
+                        ChannelDescription::Blue => Channel::Blue,
+                        ChannelDescription::Luminance => Channel::Luminance,
+                    };
+                    parameter_maps.push(ParameterMap { parameter, coloration, channel });
+                    Ok::<f32, String>(*value)
+                },
+            }
+        };
+
+        let albedo = parameter(&description.albedo, Parameter::Albedo, "albedo")?;
+        let surface = match &description.surface {
+            SurfaceDescription::Diffuse => SurfaceType::Diffuse,
+            SurfaceDescription::Reflective { reflectivity } => SurfaceType::Reflective {
+                reflectivity: parameter(reflectivity, Parameter::Reflectivity, "surface.reflectivity")?,
+            },
+            SurfaceDescription::Refractive { index, transparency, absorption } => SurfaceType::Refractive {
+                index: parameter(index, Parameter::Index, "surface.index")?,
+                transparency: parameter(transparency, Parameter::Transparency, "surface.transparency")?,
+                absorption: *absorption,
+            },
+            SurfaceDescription::Microfacet { roughness, metallic } => {
+                let roughness = parameter(roughness, Parameter::Roughness, "surface.roughness")?;
+                let metallic = parameter(metallic, Parameter::Metallic, "surface.metallic")?;
+                if !(0.0..=1.0).contains(&roughness) {
+                    return Err(format!("material: {}.surface.roughness: must be between 0 and 1, got {}", material_key, roughness));
+                }
+                if !(0.0..=1.0).contains(&metallic) {
+                    return Err(format!("material: {}.surface.metallic: must be between 0 and 1, got {}", material_key, metallic));
+                }
+                SurfaceType::Microfacet { roughness, metallic }
+            },
+        };
+
+        if description.specular_exponent < 0.0 {
+            return Err(format!("material: {}.specular_exponent: must not be negative, got {}", material_key, description.specular_exponent));
+        }
+
+        let normal_map = match &description.normal_map {
+            Some(normal_map) => {
+                let key = format!("{}.normal_map", material_key);
+                let coloration = build_texture_reference(&normal_map.texture, &key, textures)
+                    .map_err(|e| format!("material: {}", e))?;
+                Some(NormalMap { coloration, strength: normal_map.strength })
+            },
+            None => None,
+        };
+        let bump_map = match &description.bump_map {
+            Some(bump_map) => {
+                let key = format!("{}.bump_map.height", material_key);
+                let height = build_coloration(&bump_map.height, &key, textures)
+                    .map_err(|e| format!("material: {}", e))?;
+                Some(BumpMap { height, scale: bump_map.scale })
+            },
+            None => None,
+        };
+
+        Ok(Material {
+            coloration,
+            albedo,
+            surface,
+            specular_color: to_color(&description.specular_color),
+            specular_exponent: description.specular_exponent,
+            normal_map,
+            bump_map,
+            parameter_maps,
+        })
+    }
+}
+
+// Errors are prefixed with `key`, the key of the coloration in the scene file.
+fn build_coloration(description: &ColorationDescription, key: &str, textures: &HashMap<&str, Texture>) -> Result<Coloration, String> {
+    let coloration = match description {
+        ColorationDescription::Color(color) => Coloration::Color(to_color(color)),
+        ColorationDescription::Texture(reference) => build_texture_reference(reference, key, textures)?,
+        ColorationDescription::Pattern(pattern) => {
+            let key = format!("{}.pattern", key);
+            if pattern.scale <= 0.0 {
+                return Err(format!("{}.scale: must be greater than zero, got {}", key, pattern.scale));
+            }
+            let noisy = matches!(pattern.kind, PatternKind::Turbulence | PatternKind::Marble);
+            if pattern.octaves.is_some() && !noisy {
+                return Err(format!("{}.octaves: only turbulence and marble have octaves", key));
+            }
+            let distorted = matches!(pattern.kind, PatternKind::Marble | PatternKind::Wood);
+            if pattern.distortion.is_some() && !distorted {
+                return Err(format!("{}.distortion: only marble and wood are distorted", key));
+            }
+            let octaves = pattern.octaves.unwrap_or(DEFAULT_OCTAVES);
+            let distortion = pattern.distortion.unwrap_or(DEFAULT_DISTORTION);
+
+            let kind = match pattern.kind {
+                PatternKind::Checker => Pattern::Checker,
+                PatternKind::Stripes => Pattern::Stripes,
+                PatternKind::Gradient => Pattern::Gradient,
+                PatternKind::Noise => Pattern::Noise,
+                PatternKind::Turbulence => Pattern::Turbulence { octaves },
+                PatternKind::Marble => Pattern::Marble { octaves, distortion },
+                PatternKind::Wood => Pattern::Wood { distortion },
+            };
+            // solid patterns are carved out of the scene unless told otherwise
+            let domain = match pattern.domain {
+                Some(DomainDescription::Uv) => Domain::Uv,
+                Some(DomainDescription::Position) => Domain::Position,
+                None if matches!(pattern.kind, PatternKind::Checker | PatternKind::Stripes | PatternKind::Gradient) => Domain::Uv,
+                None => Domain::Position,
+            };
+            let [first, second] = &pattern.colors;
+            let colors = [
+                build_coloration(first, &format!("{}.colors[0]", key), textures)?,
+                build_coloration(second, &format!("{}.colors[1]", key), textures)?,
+            ];
+            Coloration::Procedural(Box::new(Procedural { pattern: kind, domain, scale: pattern.scale, colors }))
+        },
+        ColorationDescription::Transform(transform) => {
+            let key = format!("{}.transform", key);
+            let coloration = build_coloration(&transform.coloration, &format!("{}.coloration", key), textures)?;
+            let uv_transform = uv_transform(transform.scale, transform.rotate, transform.offset).map_err(|e| format!("{}.{}", key, e))?;
+            Coloration::Transformed(Box::new(coloration), uv_transform)
+        },
+    };
+    Ok(coloration)
+}
+
+fn build_texture_reference(reference: &TextureReference, key: &str, textures: &HashMap<&str, Texture>) -> Result<Coloration, String> {
+    let texture_name = reference.name();
+    let texture = textures
+        .get(texture_name)
+        .ok_or_else(|| format!("{}.texture: unknown texture `{}`", key, texture_name))?;
+    let coloration = Coloration::Texture(texture.clone());
+    Ok(match reference {
+        TextureReference::Name(_) => coloration,
+        TextureReference::Transformed(TransformedTexture { scale, rotate, offset, .. }) => {
+            let transform = uv_transform(*scale, *rotate, *offset).map_err(|e| format!("{}.texture.{}", key, e))?;
+            Coloration::Transformed(Box::new(coloration), transform)
+        },
+    })
+}
+
+fn uv_transform(scale: [f32; 2], rotate: f32, offset: [f32; 2]) -> Result<UvTransform, String> {
+    if scale.contains(&0.0) {
+        return Err(format!("scale: must not be zero, got {:?}", scale));
+    }
+    Ok(UvTransform { scale, rotation: rotate.to_radians(), offset })
+}
+
+// Scaled, then rotated about the x, y and z axes in that order (degrees), then
+// translated.
+fn build_transform(translate: &[f64; 3], rotate: &[f64; 3], scale: &[f64; 3]) -> Result<Transform, String> {
+    let [rx, ry, rz] = rotate.map(f64::to_radians);
+    let matrix = Matrix4::translation(to_vector(translate))
+        * Matrix4::rotation_z(rz)
+        * Matrix4::rotation_y(ry)
+        * Matrix4::rotation_x(rx)
+        * Matrix4::scaling(to_vector(scale));
+    Transform::new(matrix).ok_or_else(|| String::from("scale: must not be zero"))
+}
+
+fn build_light(light: &LightDescription, base_dir: &Path) -> Result<Light, String> {
+    let light = match light {
+        LightDescription::Directional { direction, color, intensity } =>
+            Light::DirectionalLight(DirectionalLight::new(to_vector(direction), to_color(color), *intensity)),
+        LightDescription::Spherical { position, color, intensity } =>
+            Light::SphericalLight(SphericalLight::new(to_point(position), to_color(color), *intensity)),
+        LightDescription::Spot { position, direction, inner_angle, outer_angle, color, intensity, profile } => {
+            let direction = to_vector(direction);
+            if direction.length() == 0.0 {
+                return Err(String::from("direction: must not be the zero vector"));
+            }
+            if !(*outer_angle > 0.0 && *outer_angle <= 180.0) {
+                return Err(format!("outer_angle: must be between 0 and 180 degrees, got {}", outer_angle));
+            }
+            if !(*inner_angle >= 0.0 && *inner_angle <= *outer_angle) {
+                return Err(format!("inner_angle: must be between 0 and outer_angle, got {}", inner_angle));
+            }
+            let mut spot = SpotLight::new(to_point(position), direction, *inner_angle, *outer_angle, to_color(color), *intensity);
+            if let Some(profile) = profile {
+                let profile = ies::load_ies(&base_dir.join(profile)).map_err(|e| format!("profile: {}", e))?;
+                spot = spot.with_profile(profile);
+            }
+            Light::SpotLight(spot)
+        },
+        LightDescription::Rectangle { position, edge_u, edge_v, color, intensity, samples } => {
+            let (edge_u, edge_v) = (to_vector(edge_u), to_vector(edge_v));
+            if edge_u.cross(&edge_v).length() == 0.0 {
+                return Err(String::from("edge_u: edges must not be parallel or zero"));
+            }
+            Light::RectangleLight(RectangleLight::new(to_point(position), edge_u, edge_v, to_color(color), *intensity, *samples))
+        },
+        LightDescription::Disk { position, normal, radius, color, intensity, samples } => {
+            if *radius <= 0.0 {
+                return Err(format!("radius: must be greater than zero, got {}", radius));
+            }
+            let normal = to_vector(normal);
+            if normal.length() == 0.0 {
+                return Err(String::from("normal: must not be the zero vector"));
+            }
+            Light::DiskLight(DiskLight::new(to_point(position), normal, *radius, to_color(color), *intensity, *samples))
+        },
+        LightDescription::Sphere { position, radius, color, intensity, samples } => {
+            if *radius <= 0.0 {
+                return Err(format!("radius: must be greater than zero, got {}", radius));
+            }
+            Light::SphereLight(SphereLight::new(to_point(position), *radius, to_color(color), *intensity, *samples))
+        },
+    };
+    Ok(light)
+}
+
+fn to_point(p: &[f64; 3]) -> Point {
+    Point::new(p[0], p[1], p[2])
+}
+
+fn to_vector(v: &[f64; 3]) -> Vector3 {
+    Vector3::new(v[0], v[1], v[2])
+}
+
+fn to_color(c: &[f32; 3]) -> Color {
+    Color::new(c[0], c[1], c[2])
+}
+
+pub fn load(path: &Path) -> Result<Scene, String> {
+    let description = SceneDescription::from_file(path)?;
+    let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+    description.build(base_dir).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CAMERA: &str = "[camera]\nwidth = 4\nheight = 4\n";
+
+    fn parse_error(source: &str) -> String {
+        match SceneDescription::parse(&format!("{}{}", CAMERA, source)) {
+            Ok(_) => panic!("`{}` should not parse", source),
+            Err(e) => e,
+        }
+    }
+
+    fn build_error(source: &str) -> String {
+        match SceneDescription::parse(source).unwrap().build(Path::new("")) {
+            Ok(_) => panic!("`{}` should not build", source),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn build_errors_report_the_line_of_the_entry() {
+        let error = build_error("[camera]\nwidth = 0\nheight = 4\n");
+        assert!(error.starts_with("line 1: camera:"), "{}", error);
+
+        let elements = "[materials.red]\ncoloration = { color = [1.0, 0.0, 0.0] }\nalbedo = 0.18\n\n\
+            [[elements]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -5.0]\nradius = 1.0\nmaterial = \"red\"\n\n\
+            [[elements]]\ntype = \"sphere\"\ncenter = [0.0, 0.0, -5.0]\nradius = -1.0\nmaterial = \"red\"\n";
+        let error = build_error(&format!("{}{}", CAMERA, elements));
+        assert!(error.starts_with("line 14: elements[1].radius:"), "{}", error);
+
+        let lights = "\n[[lights]]\ntype = \"spherical\"\nposition = [0.0, 0.0, 0.0]\ncolor = [1.0, 1.0, 1.0]\nintensity = 1.0\n\n\
+            [[lights]]\ntype = \"rectangle\"\nposition = [0.0, 0.0, 0.0]\nedge_u = [0.0, 0.0, 0.0]\n\
+            edge_v = [0.0, 1.0, 0.0]\ncolor = [1.0, 1.0, 1.0]\nintensity = 1.0\n";
+        let error = build_error(&format!("{}{}", CAMERA, lights));
+        assert!(error.starts_with("line 11: lights[1]."), "{}", error);
+    }
+
+    #[test]
+    fn textures_are_a_path_or_a_table() {
+        let description = SceneDescription::parse(&format!("{}{}", CAMERA,
+            "[textures]\nwood = \"wood.png\"\nnormals = { path = \"normals.png\", filter = \"nearest\", color_space = \"linear\" }\n")).unwrap();
+        assert!(matches!(description.textures["wood"].get_ref(), TextureDescription::Path(path) if path == Path::new("wood.png")));
+        assert!(matches!(description.textures["normals"].get_ref(),
+            TextureDescription::Table(TextureTable { filter: FilterDescription::Nearest, color_space: Some(ColorSpaceDescription::Linear), .. })));
+
+        let error = parse_error("[textures]\nwood = { path = \"wood.png\", filtr = \"nearest\" }\n");
+        assert!(error.contains("unknown field `filtr`"), "{}", error);
+        let error = parse_error("[textures]\nwood = { filter = \"nearest\" }\n");
+        assert!(error.contains("missing field `path`"), "{}", error);
+    }
+
+    #[test]
+    fn texture_references_reject_unknown_fields() {
+        let material = "[materials.wood]\nalbedo = 0.18\ncoloration = { texture = { name = \"wood\", rotation = 30 } }\n";
+        let error = parse_error(material);
+        assert!(error.contains("unknown field `rotation`"), "{}", error);
+    }
+
+    #[test]
+    fn any_coloration_can_be_transformed() {
+        let description = SceneDescription::parse(&format!("{}{}", CAMERA, "[materials.floor]\nalbedo = 0.18\n\
+            coloration = { transform = { scale = [4.0, 4.0], rotate = 45.0, coloration = { pattern = { type = \"checker\", \
+            colors = [{ color = [0.0, 0.0, 0.0] }, { color = [1.0, 1.0, 1.0] }] } } } }\n")).unwrap();
+        let material = description.materials["floor"].get_ref();
+        let coloration = build_coloration(&material.coloration, "floor", &HashMap::new()).unwrap();
+        // the checker of a 4 times denser, rotated grid
+        let color = |x, y| coloration.color(&TextureCoords { x, y }, &Point::zero()).red;
+        assert_ne!(color(0.05, 0.05), color(0.05, 0.2));
+        assert!(matches!(coloration, Coloration::Transformed(_, UvTransform { scale: [4.0, 4.0], .. })));
+    }
+
+    #[test]
+    fn parameters_are_a_number_or_a_map() {
+        let description = SceneDescription::parse(&format!("{}{}", CAMERA, "[materials.metal]\n\
+            coloration = { color = [1.0, 1.0, 1.0] }\nalbedo = 1\n\
+            surface = { type = \"microfacet\", roughness = { map = { texture = \"rough\" }, channel = \"green\" }, metallic = 1 }\n")).unwrap();
+        let material = description.materials["metal"].get_ref();
+        assert!(matches!(material.albedo, ParameterDescription::Value(albedo) if albedo == 1.0));
+        assert!(matches!(material.parameters()[1],
+            ParameterDescription::Map(ParameterMapDescription { value, channel: ChannelDescription::Green, .. }) if *value == 1.0));
+
+        let error = parse_error("[materials.metal]\ncoloration = { color = [1.0, 1.0, 1.0] }\n\
+            albedo = { map = { texture = \"rough\" }, chanel = \"red\" }\n");
+        assert!(error.contains("unknown field `chanel`"), "{}", error);
+    }
+
+    #[test]
+    fn maps_read_textures_as_linear_data() {
+        let materials = "[materials.rough]\ncoloration = { texture = \"albedo\" }\nalbedo = 1.0\n\
+            surface = { type = \"microfacet\", roughness = { map = { texture = \"roughness\" } } }\n\
+            normal_map = { texture = { name = \"normals\", scale = [2.0, 2.0] } }\n";
+        let description = SceneDescription::parse(&format!("{}{}", CAMERA, materials)).unwrap();
+        assert_eq!(description.data_textures(), HashSet::from(["roughness", "normals"]));
+
+        let textures = "[textures]\nroughness = { path = \"roughness.png\", color_space = \"srgb\" }\n";
+        let description = SceneDescription::parse(&format!("{}{}{}", CAMERA, textures, materials)).unwrap();
+        let error = description.build(Path::new("")).err().unwrap();
+        assert!(error.contains("textures.roughness.color_space: read as data"), "{}", error);
+    }
+}
@@ -0,0 +1,111 @@
+use super::material::Color;
+
+// Display transform compressing linear radiance into [0, 1] before it is
+// encoded to 8 bits. Floating-point outputs are written without it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    Clamp,            // clip everything above 1
+    Reinhard,         // L / (1 + L) on luminance
+    ExtendedReinhard, // Reinhard reaching white at the white point
+    Aces,             // Narkowicz's fit of the ACES filmic curve
+    Hable,            // Uncharted 2 filmic curve
+}
+
+impl ToneMapping {
+
+    // `exposure` is in stops; `white_point` is the luminance mapped to white
+    // by the extended Reinhard operator.
+    pub fn apply(&self, color: Color, exposure: f32, white_point: f32) -> Color {
+        let color = color * exposure.exp2();
+        match self {
+            ToneMapping::Clamp => color.clamp(),
+            ToneMapping::Reinhard => scale_luminance(color, |l| l / (1.0 + l)),
+            ToneMapping::ExtendedReinhard => {
+                let white2 = white_point * white_point;
+                scale_luminance(color, |l| l * (1.0 + l / white2) / (1.0 + l))
+            },
+            ToneMapping::Aces => map_channels(color, aces),
+            ToneMapping::Hable => {
+                const WHITE: f32 = 11.2;
+                const EXPOSURE_BIAS: f32 = 2.0;
+                let white_scale = hable(WHITE).recip();
+                map_channels(color, |x| hable(x * EXPOSURE_BIAS) * white_scale)
+            },
+        }.clamp()
+    }
+}
+
+// Maps the luminance and scales the channels along, keeping the hue.
+fn scale_luminance<F: Fn(f32) -> f32>(color: Color, curve: F) -> Color {
+    let luminance = color.luminance();
+    if luminance <= 0.0 {
+        return Color::black();
+    }
+    color * (curve(luminance) / luminance)
+}
+
+fn map_channels<F: Fn(f32) -> f32>(color: Color, curve: F) -> Color {
+    Color::new(curve(color.red), curve(color.green), curve(color.blue))
+}
+
+fn aces(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+fn hable(x: f32) -> f32 {
+    const A: f32 = 0.15; // shoulder strength
+    const B: f32 = 0.50; // linear strength
+    const C: f32 = 0.10; // linear angle
+    const D: f32 = 0.20; // toe strength
+    const E: f32 = 0.02; // toe numerator
+    const F: f32 = 0.30; // toe denominator
+    ((x * (A * x + C * B) + D * E) / (x * (A * x + B) + D * F)) - E / F
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [ToneMapping; 5] = [
+        ToneMapping::Clamp,
+        ToneMapping::Reinhard,
+        ToneMapping::ExtendedReinhard,
+        ToneMapping::Aces,
+        ToneMapping::Hable,
+    ];
+
+    fn grey(operator: ToneMapping, value: f32) -> f32 {
+        let color = operator.apply(Color::new(value, value, value), 0.0, 4.0);
+        assert!((color.red - color.green).abs() < 1e-6 && (color.red - color.blue).abs() < 1e-6);
+        color.red
+    }
+
+    #[test]
+    fn operators_map_black_to_black_and_large_values_to_white() {
+        for operator in OPERATORS {
+            assert!(grey(operator, 0.0).abs() < 1e-6, "{:?}", operator);
+            assert!(grey(operator, 1e6) > 0.999, "{:?}", operator);
+            let mut previous = 0.0;
+            for i in 1..100 {
+                let value = grey(operator, i as f32 * 0.1);
+                assert!(value >= previous && value <= 1.0, "{:?}", operator);
+                previous = value;
+            }
+        }
+    }
+
+    #[test]
+    fn operators_at_one() {
+        assert_eq!(grey(ToneMapping::Clamp, 1.0), 1.0);
+        assert!((grey(ToneMapping::Reinhard, 1.0) - 0.5).abs() < 1e-6);
+        assert!((grey(ToneMapping::ExtendedReinhard, 1.0) - 0.5 * (1.0 + 1.0 / 16.0)).abs() < 1e-6);
+        assert!((grey(ToneMapping::ExtendedReinhard, 4.0) - 1.0).abs() < 1e-6);
+        assert!((grey(ToneMapping::Aces, 1.0) - 2.54 / 3.16).abs() < 1e-6);
+        let hable = grey(ToneMapping::Hable, 1.0);
+        assert!(hable > 0.0 && hable < 1.0);
+        // one stop of exposure doubles the input
+        let reinhard = ToneMapping::Reinhard.apply(Color::new(0.5, 0.5, 0.5), 1.0, 4.0);
+        assert!((reinhard.red - 0.5).abs() < 1e-6);
+    }
+}